@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::{Color, Point};
+use crate::{
+    render::{decode_image, grayscale_mask, polygon_contains, ratio},
+    Color, Point,
+};
 
 /// The data structure that holds information about how to draw a stroke.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -20,6 +24,8 @@ pub struct Brush {
     /// [`u32::MAX`] is 1 and 0 is 0.
     pub opacity: u32,
     /// Wether to erase or not.
+    ///
+    /// An erasing brush ignores its blend mode.
     pub erase: bool,
     /// How often to repeat the brush, relative to the brush `width`.
     ///
@@ -27,6 +33,125 @@ pub struct Brush {
     /// If `repeat` is `1`, repeat once every `1/width` pixels.
     /// Can't be 0.
     pub repeat: u32,
+    /// How the stylus changes the brush along a stroke.
+    #[serde(default)]
+    pub dynamics: BrushDynamics,
+    /// How much every stamp randomly differs from the others.
+    #[serde(default)]
+    pub jitter: BrushJitter,
+    /// The grain of the paper, if any.
+    #[serde(default)]
+    pub texture: Option<BrushTexture>,
+    /// How the paint is mixed with the pixels already on the layer.
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+}
+
+/// How paint is mixed with the pixels already on a layer.
+///
+/// A stroke is blended as a whole: its stamps are first painted normally on
+/// top of each other, then the result is blended with the layer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Paints over the pixels.
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    /// Adds the colors.
+    Add,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl BlendMode {
+    fn to_skia(self) -> tiny_skia::BlendMode {
+        match self {
+            BlendMode::Normal => tiny_skia::BlendMode::SourceOver,
+            BlendMode::Multiply => tiny_skia::BlendMode::Multiply,
+            BlendMode::Screen => tiny_skia::BlendMode::Screen,
+            BlendMode::Overlay => tiny_skia::BlendMode::Overlay,
+            BlendMode::Darken => tiny_skia::BlendMode::Darken,
+            BlendMode::Lighten => tiny_skia::BlendMode::Lighten,
+            BlendMode::ColorDodge => tiny_skia::BlendMode::ColorDodge,
+            BlendMode::ColorBurn => tiny_skia::BlendMode::ColorBurn,
+            BlendMode::HardLight => tiny_skia::BlendMode::HardLight,
+            BlendMode::SoftLight => tiny_skia::BlendMode::SoftLight,
+            BlendMode::Difference => tiny_skia::BlendMode::Difference,
+            BlendMode::Exclusion => tiny_skia::BlendMode::Exclusion,
+            BlendMode::Add => tiny_skia::BlendMode::Plus,
+            BlendMode::Hue => tiny_skia::BlendMode::Hue,
+            BlendMode::Saturation => tiny_skia::BlendMode::Saturation,
+            BlendMode::Color => tiny_skia::BlendMode::Color,
+            BlendMode::Luminosity => tiny_skia::BlendMode::Luminosity,
+        }
+    }
+}
+
+/// How the pressure of the stylus changes a brush along a stroke.
+///
+/// Pressure only changes strokes that recorded it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BrushDynamics {
+    /// How much the pressure changes the width of the brush.
+    ///
+    /// [`u32::MAX`] is 1 and 0 is 0. With 1 the width is proportional to the
+    /// pressure, with 0 the pressure does not change the width.
+    pub pressure_width: u32,
+    /// How much the pressure changes the opacity of the brush, like `pressure_width`.
+    pub pressure_opacity: u32,
+}
+
+impl BrushDynamics {
+    /// Returns the factors to apply to the width and the opacity of the brush
+    /// for the given pressure, between 0 and 1.
+    pub fn factors(&self, pressure: f32) -> (f32, f32) {
+        let pressure = pressure.clamp(0.0, 1.0);
+        (
+            1.0 - ratio(self.pressure_width) * (1.0 - pressure),
+            1.0 - ratio(self.pressure_opacity) * (1.0 - pressure),
+        )
+    }
+}
+
+/// How much every stamp of a stroke randomly differs from the others.
+///
+/// The random values come from the seed of the stroke, so a stroke is always
+/// drawn the same way. Every value is a ratio where [`u32::MAX`] is 1 and 0 is 0.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BrushJitter {
+    /// How far stamps are moved from the stroke, 1 being up to one brush width away.
+    pub scatter: u32,
+    /// How much stamps shrink, 1 being down to nothing.
+    pub size: u32,
+    /// How much stamps fade, 1 being down to transparent.
+    pub opacity: u32,
+    /// How much stamps rotate, 1 being up to a full turn.
+    pub angle: u32,
+}
+
+/// A grayscale image tiled over the canvas that strokes are painted through,
+/// like the grain of a paper.
+///
+/// Black lets the brush paint, white stops it and grays let it paint
+/// partially. The texture stays in place on the canvas, it does not follow
+/// the stamps.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrushTexture {
+    bitmap: BrushBitmap,
+    /// How much the texture shows. [`u32::MAX`] is 1 and 0 is 0.
+    pub depth: u32,
 }
 
 impl Default for Brush {
@@ -39,6 +164,10 @@ impl Default for Brush {
             opacity: 100_000,
             erase: false,
             repeat: 2,
+            dynamics: Default::default(),
+            jitter: Default::default(),
+            texture: None,
+            blend_mode: BlendMode::Normal,
         }
     }
 }
@@ -62,6 +191,10 @@ impl Brush {
             opacity,
             erase,
             repeat,
+            dynamics: Default::default(),
+            jitter: Default::default(),
+            texture: None,
+            blend_mode: BlendMode::Normal,
         }
     }
 
@@ -99,6 +232,69 @@ impl Brush {
     pub fn set_repeat(&mut self, repeat: u32) {
         self.repeat = repeat;
     }
+
+    /// Updates the dynamics of the brush.
+    pub fn set_dynamics(&mut self, dynamics: BrushDynamics) {
+        self.dynamics = dynamics;
+    }
+
+    /// Updates the jitter of the brush.
+    pub fn set_jitter(&mut self, jitter: BrushJitter) {
+        self.jitter = jitter;
+    }
+
+    /// Updates the texture of the brush.
+    pub fn set_texture(&mut self, texture: Option<BrushTexture>) {
+        self.texture = texture;
+    }
+
+    /// Updates the blend mode of the brush.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    /// Returns how the rasterizer mixes the paint of the brush with the layer.
+    pub(crate) fn skia_blend_mode(&self) -> tiny_skia::BlendMode {
+        if self.erase {
+            tiny_skia::BlendMode::DestinationOut
+        } else {
+            self.blend_mode.to_skia()
+        }
+    }
+
+    /// Checks that the brush can be drawn.
+    pub fn validate(&self) -> Result<(), BrushError> {
+        if !(0.0..=MAX_BRUSH_WIDTH).contains(&self.width) {
+            return Err(BrushError::InvalidWidth(self.width));
+        }
+        if self.repeat == 0 {
+            return Err(BrushError::ZeroRepeat);
+        }
+        if let Some(texture) = &self.texture {
+            texture.bitmap.validate()?;
+        }
+        match &self.brush_shape {
+            BrushShape::Custom(custom) => custom.validate(),
+            BrushShape::Circle | BrushShape::Square => Ok(()),
+        }
+    }
+}
+
+/// A named brush, shared by the users of a drawing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrushPreset {
+    /// The name of the preset, unique in its drawing.
+    pub name: String,
+    pub brush: Brush,
+}
+
+impl BrushPreset {
+    pub fn new(name: &str, brush: Brush) -> Self {
+        BrushPreset {
+            name: String::from(name),
+            brush,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -112,11 +308,279 @@ pub enum BrushShape {
 }
 
 /// A custom brush shape.
-/// TODO: make a more extensible custom brush.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+///
+/// The shape is stamped along strokes like the other shapes, in a square of
+/// side [`Brush::width`] centered on the stroke. It is made either of polygons
+/// filled with the brush color, or of a grayscale bitmap where black paints
+/// with the brush color, white leaves the canvas untouched and grays paint
+/// partially. The bitmap is used when both are present.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CustomBrush {
     /// A collection of collections of points
     /// which represent shapes. The last point
     /// is linked to the first.
+    ///
+    /// Coordinates are relative to the brush square: (0, 0) is its top left
+    /// corner and (1, 1) its bottom right corner.
+    #[serde(default)]
     points: Vec<Vec<Point>>,
+    /// A grayscale stamp.
+    #[serde(default)]
+    bitmap: Option<BrushBitmap>,
+}
+
+/// A grayscale image used as a brush stamp.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrushBitmap {
+    width: u32,
+    height: u32,
+    /// One gray value per pixel, row by row, 0 being black.
+    data: Vec<u8>,
+}
+
+/// The largest width of a brush.
+pub const MAX_BRUSH_WIDTH: f32 = 1024.0;
+
+/// The largest width and height of a brush bitmap.
+pub const MAX_BRUSH_BITMAP_SIZE: u32 = 1024;
+
+#[derive(Error, Debug)]
+pub enum BrushError {
+    #[error("custom brush has no shape")]
+    EmptyShape,
+    #[error("invalid polygon {0}, polygons must have at least 3 finite points")]
+    InvalidPolygon(usize),
+    #[error("invalid {0}x{1} brush bitmap, sides must be between 1 and {MAX_BRUSH_BITMAP_SIZE}")]
+    InvalidBitmapSize(u32, u32),
+    #[error("brush bitmap has {actual} pixels instead of {expected}")]
+    InvalidBitmapData { expected: usize, actual: usize },
+    #[error("invalid brush image: {0}")]
+    InvalidImage(String),
+    #[error("invalid brush width {0}, widths must be between 0 and {MAX_BRUSH_WIDTH}")]
+    InvalidWidth(f32),
+    #[error("invalid brush repeat, repeat cannot be 0")]
+    ZeroRepeat,
+}
+
+impl CustomBrush {
+    /// Creates a brush from polygons, in any coordinate system.
+    ///
+    /// The polygons are scaled and centered so that they fit the brush square.
+    pub fn from_polygons(polygons: Vec<Vec<Point>>) -> Result<Self, BrushError> {
+        validate_polygons(&polygons)?;
+        let points = polygons.iter().flatten();
+        let (min_x, min_y, max_x, max_y) = points.fold(
+            (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+            |(min_x, min_y, max_x, max_y), p| {
+                (min_x.min(p.x), min_y.min(p.y), max_x.max(p.x), max_y.max(p.y))
+            },
+        );
+        let size = (max_x - min_x).max(max_y - min_y);
+        let scale = if size > 0.0 { 1.0 / size } else { 1.0 };
+        let (offset_x, offset_y) = (
+            (1.0 - (max_x - min_x) * scale) / 2.0,
+            (1.0 - (max_y - min_y) * scale) / 2.0,
+        );
+        let points = polygons
+            .into_iter()
+            .map(|polygon| {
+                polygon
+                    .into_iter()
+                    .map(|p| {
+                        Point::new(
+                            (p.x - min_x) * scale + offset_x,
+                            (p.y - min_y) * scale + offset_y,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(CustomBrush {
+            points,
+            bitmap: None,
+        })
+    }
+
+    /// Creates a brush from a grayscale bitmap, one byte per pixel, row by row.
+    ///
+    /// Bitmaps that are not square are centered in the brush square.
+    pub fn from_bitmap(width: u32, height: u32, data: Vec<u8>) -> Result<Self, BrushError> {
+        let bitmap = BrushBitmap {
+            width,
+            height,
+            data,
+        };
+        bitmap.validate()?;
+        Ok(CustomBrush {
+            points: vec![],
+            bitmap: Some(bitmap),
+        })
+    }
+
+    /// Creates a brush from an image stored as a data URL (or as raw base64).
+    ///
+    /// The image is put over a white background and converted to grayscale.
+    pub fn from_image(data: &str) -> Result<Self, BrushError> {
+        let image = decode_image(data).map_err(|e| BrushError::InvalidImage(e.to_string()))?;
+        let mask = grayscale_mask(&image).map_err(|e| BrushError::InvalidImage(e.to_string()))?;
+        CustomBrush::from_bitmap(image.width(), image.height(), mask.data().to_vec())
+    }
+
+    /// Gets the shapes making up the brush.
+    pub fn polygons(&self) -> &[Vec<Point>] {
+        &self.points
+    }
+
+    /// Gets the grayscale stamp of the brush, if any.
+    pub fn bitmap(&self) -> Option<&BrushBitmap> {
+        self.bitmap.as_ref()
+    }
+
+    /// Checks that the brush has a shape that can be drawn.
+    pub fn validate(&self) -> Result<(), BrushError> {
+        match &self.bitmap {
+            Some(bitmap) => bitmap.validate(),
+            None => validate_polygons(&self.points),
+        }
+    }
+
+    /// Returns how much the brush paints at the given point of the brush
+    /// square, between 0 and 1.
+    pub fn coverage(&self, x: f32, y: f32) -> f32 {
+        match &self.bitmap {
+            Some(bitmap) => {
+                let side = bitmap.width.max(bitmap.height) as f32;
+                let px = x * side - (side - bitmap.width as f32) / 2.0;
+                let py = y * side - (side - bitmap.height as f32) / 2.0;
+                if px < 0.0 || py < 0.0 || px >= bitmap.width as f32 || py >= bitmap.height as f32
+                {
+                    return 0.0;
+                }
+                let gray = bitmap.data[py as usize * bitmap.width as usize + px as usize];
+                1.0 - gray as f32 / 255.0
+            }
+            None => {
+                let point = Point::new(x, y);
+                let inside = self
+                    .points
+                    .iter()
+                    .filter(|polygon| polygon_contains(polygon, &point))
+                    .count();
+                // Polygons are filled with the even-odd rule.
+                (inside % 2) as f32
+            }
+        }
+    }
+}
+
+impl BrushTexture {
+    /// Creates a texture from a grayscale bitmap, one byte per pixel, row by row.
+    pub fn from_bitmap(
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+        depth: u32,
+    ) -> Result<Self, BrushError> {
+        let bitmap = BrushBitmap {
+            width,
+            height,
+            data,
+        };
+        bitmap.validate()?;
+        Ok(BrushTexture { bitmap, depth })
+    }
+
+    /// Creates a texture from an image stored as a data URL (or as raw base64).
+    ///
+    /// The image is put over a white background and converted to grayscale.
+    pub fn from_image(data: &str, depth: u32) -> Result<Self, BrushError> {
+        let image = decode_image(data).map_err(|e| BrushError::InvalidImage(e.to_string()))?;
+        let mask = grayscale_mask(&image).map_err(|e| BrushError::InvalidImage(e.to_string()))?;
+        BrushTexture::from_bitmap(image.width(), image.height(), mask.data().to_vec(), depth)
+    }
+
+    /// Gets the grayscale image of the texture.
+    pub fn bitmap(&self) -> &BrushBitmap {
+        &self.bitmap
+    }
+
+    /// Returns how much the brush paints through the texture at the given
+    /// pixel of the canvas, between 0 and 1.
+    pub fn coverage(&self, x: u32, y: u32) -> f32 {
+        let BrushBitmap {
+            width,
+            height,
+            data,
+        } = &self.bitmap;
+        let gray = data[((y % height) * width + x % width) as usize];
+        1.0 - ratio(self.depth) * gray as f32 / 255.0
+    }
+}
+
+impl BrushBitmap {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Gets the gray value of every pixel, row by row, 0 being black.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn validate(&self) -> Result<(), BrushError> {
+        let valid = 1..=MAX_BRUSH_BITMAP_SIZE;
+        if !valid.contains(&self.width) || !valid.contains(&self.height) {
+            return Err(BrushError::InvalidBitmapSize(self.width, self.height));
+        }
+        let expected = (self.width * self.height) as usize;
+        if self.data.len() != expected {
+            return Err(BrushError::InvalidBitmapData {
+                expected,
+                actual: self.data.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn validate_polygons(polygons: &[Vec<Point>]) -> Result<(), BrushError> {
+    if polygons.is_empty() {
+        return Err(BrushError::EmptyShape);
+    }
+    for (index, polygon) in polygons.iter().enumerate() {
+        if polygon.len() < 3 || polygon.iter().any(|p| !p.x.is_finite() || !p.y.is_finite()) {
+            return Err(BrushError::InvalidPolygon(index));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brushes_are_bounded() {
+        let brush = |width: f32, repeat: u32| Brush {
+            width,
+            repeat,
+            ..Default::default()
+        };
+        assert!(brush(0.0, 1).validate().is_ok());
+        assert!(brush(MAX_BRUSH_WIDTH, u32::MAX).validate().is_ok());
+        for width in [-1.0, MAX_BRUSH_WIDTH + 1.0, f32::INFINITY, f32::NAN] {
+            assert!(matches!(
+                brush(width, 1).validate(),
+                Err(BrushError::InvalidWidth(_))
+            ));
+        }
+        assert!(matches!(
+            brush(10.0, 0).validate(),
+            Err(BrushError::ZeroRepeat)
+        ));
+    }
 }
@@ -1,7 +1,10 @@
+use std::{fmt, str::FromStr};
+
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 /// An RGBA color.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Color {
     /// The amount of red.
     pub r: u8,
@@ -9,10 +12,228 @@ pub struct Color {
     pub g: u8,
     /// The amount of blue.
     pub b: u8,
+    /// The opacity, 255 being opaque. Defaults to 255 for colors saved without alpha.
+    #[serde(default = "opaque")]
+    pub a: u8,
+}
+
+fn opaque() -> u8 {
+    u8::MAX
+}
+
+#[derive(Error, Debug)]
+pub enum ColorError {
+    #[error("invalid hex color {0}, expected #rgb, #rgba, #rrggbb or #rrggbbaa")]
+    InvalidHex(String),
 }
 
 impl Default for Color {
     fn default() -> Self {
-        Color { r: 0, g: 0, b: 0 }
+        Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        }
+    }
+}
+
+impl Color {
+    /// Creates an opaque color.
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b, a: 255 }
+    }
+
+    pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+
+    /// Creates a color from its hue (in degrees), saturation and value (between 0 and 1).
+    pub fn from_hsv(h: f32, s: f32, v: f32, a: u8) -> Self {
+        let (s, v) = (s.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+        let l = v * (1.0 - s / 2.0);
+        let s = if l == 0.0 || l == 1.0 {
+            0.0
+        } else {
+            (v - l) / l.min(1.0 - l)
+        };
+        Color::from_hsl(h, s, l, a)
+    }
+
+    /// Returns the hue (in degrees), saturation and value (between 0 and 1) of the color.
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let (h, s, l) = self.to_hsl();
+        let v = l + s * l.min(1.0 - l);
+        let s = if v == 0.0 { 0.0 } else { 2.0 * (1.0 - l / v) };
+        (h, s, v)
+    }
+
+    /// Creates a color from its hue (in degrees), saturation and lightness (between 0 and 1).
+    pub fn from_hsl(h: f32, s: f32, l: f32, a: u8) -> Self {
+        let [r, g, b] = hsl_to_rgb(h, s.clamp(0.0, 1.0), l.clamp(0.0, 1.0))
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        Color { r, g, b, a }
+    }
+
+    /// Returns the hue (in degrees), saturation and lightness (between 0 and 1) of the color.
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        rgb_to_hsl([self.r, self.g, self.b].map(|c| c as f32 / 255.0))
+    }
+
+    /// Parses a `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` color, the `#` being optional.
+    pub fn from_hex(hex: &str) -> Result<Self, ColorError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        let invalid = || ColorError::InvalidHex(hex.to_string());
+        if !digits.is_ascii() {
+            return Err(invalid());
+        }
+        let channels = match digits.len() {
+            3 | 4 => digits
+                .chars()
+                .map(|c| c.to_digit(16).map(|d| d as u8 * 17))
+                .collect::<Option<Vec<_>>>(),
+            6 | 8 => (0..digits.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        }
+        .ok_or_else(invalid)?;
+        Ok(Color {
+            r: channels[0],
+            g: channels[1],
+            b: channels[2],
+            a: channels.get(3).copied().unwrap_or(255),
+        })
+    }
+
+    /// Formats the color as `#rrggbb`, or `#rrggbbaa` if it is not opaque.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl FromStr for Color {
+    type Err = ColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Color::from_hex(s)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts RGB channels (between 0 and 1) to hue (in degrees), saturation and lightness.
+pub(crate) fn rgb_to_hsl([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (h, s, l)
+}
+
+/// Converts hue (in degrees), saturation and lightness to RGB channels (between 0 and 1).
+pub(crate) fn hsl_to_rgb(h: f32, s: f32, l: f32) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = chroma * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = l - chroma / 2.0;
+    let (r, g, b) = match (h.rem_euclid(360.0) / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A spread of colors covering every hue sector, grays and transparency.
+    fn samples() -> Vec<Color> {
+        let mut colors = vec![];
+        for r in (0..=255).step_by(51) {
+            for g in (0..=255).step_by(85) {
+                for b in (0..=255).step_by(17) {
+                    colors.push(Color::rgba(r, g, b, r ^ b));
+                }
+            }
+        }
+        colors
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        for color in samples() {
+            assert_eq!(Color::from_hex(&color.to_hex()).unwrap(), color);
+        }
+    }
+
+    #[test]
+    fn hex_formats() {
+        assert_eq!(Color::from_hex("#fa0").unwrap(), Color::rgb(255, 170, 0));
+        assert_eq!(
+            Color::from_hex("fa08").unwrap(),
+            Color::rgba(255, 170, 0, 136)
+        );
+        assert_eq!(Color::from_hex("#0a1b2c").unwrap(), Color::rgb(10, 27, 44));
+        assert_eq!(
+            Color::from_hex("#0A1B2C3D").unwrap(),
+            Color::rgba(10, 27, 44, 61)
+        );
+        assert_eq!(Color::rgb(10, 27, 44).to_hex(), "#0a1b2c");
+        assert_eq!(Color::rgba(10, 27, 44, 61).to_hex(), "#0a1b2c3d");
+        for invalid in ["", "#", "#ab", "#abcde", "#gggggg", "#ééé"] {
+            assert!(Color::from_hex(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for color in samples() {
+            let (h, s, l) = color.to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l, color.a), color);
+        }
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for color in samples() {
+            let (h, s, v) = color.to_hsv();
+            assert_eq!(Color::from_hsv(h, s, v, color.a), color);
+        }
+    }
+
+    #[test]
+    fn hsl_and_hsv_are_clamped() {
+        assert_eq!(Color::from_hsl(0.0, 2.0, 0.5, 255), Color::rgb(255, 0, 0));
+        assert_eq!(Color::from_hsl(0.0, 1.0, -1.0, 255), Color::rgb(0, 0, 0));
+        assert_eq!(Color::from_hsv(120.0, 2.0, 2.0, 255), Color::rgb(0, 255, 0));
+        assert_eq!(
+            Color::from_hsv(120.0, -1.0, 1.0, 255),
+            Color::rgb(255, 255, 255)
+        );
     }
 }
@@ -1,9 +1,16 @@
-use std::collections::hash_map::HashMap;
+use std::{borrow::Cow, collections::hash_map::HashMap};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tiny_skia::Pixmap;
 
-use crate::{layer::LayerError, InstructionBox, Layer};
+use crate::{
+    brush::{BrushError, BrushPreset},
+    layer::LayerError,
+    render::{Compositor, RenderError},
+    BucketFill, BucketSample, Instruction, InstructionBox, Layer, Palette, PaletteError, Pattern,
+    Point, Swatch, Symmetry, SymmetryError, Timelapse,
+};
 
 /// A drawing representation as a list of instructions executed on different layers.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,9 +19,33 @@ pub struct Drawing {
     layer_order: Vec<String>,
     width: u32,
     height: u32,
+    #[serde(default)]
+    palette: Palette,
+    #[serde(default)]
+    brush_presets: Vec<BrushPreset>,
+    /// The images repeated by pattern fills, see [`Drawing::add_pattern`].
+    #[serde(default)]
+    patterns: Vec<Pattern>,
+    /// The symmetry new strokes are drawn with, if any.
+    #[serde(default)]
+    symmetry: Option<Symmetry>,
+}
+
+/// An instruction affecting a point of the drawing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstructionHit {
+    /// The layer containing the instruction.
+    pub layer: String,
+    /// The history index of the instruction in its layer.
+    pub index: u64,
+    /// The uuid of the instruction.
+    pub uuid: String,
+    /// The name of the user who created the instruction, if known.
+    pub author: Option<String>,
 }
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum DrawingError {
     #[error("could not find layer {0}")]
     LayerNotFound(String),
@@ -26,6 +57,24 @@ pub enum DrawingError {
     LayerBottom(String),
     #[error("layer error: {0}")]
     LayerError(#[from] LayerError),
+    #[error("render error: {0}")]
+    RenderError(#[from] RenderError),
+    #[error("palette error: {0}")]
+    PaletteError(#[from] PaletteError),
+    #[error("brush error: {0}")]
+    BrushError(#[from] BrushError),
+    #[error("symmetry error: {0}")]
+    SymmetryError(#[from] SymmetryError),
+    #[error("brush preset {0} already exists")]
+    BrushPresetAlreadyExists(String),
+    #[error("could not find brush preset {0}")]
+    BrushPresetNotFound(String),
+    #[error("pattern {0} already exists")]
+    PatternAlreadyExists(String),
+    #[error("could not find pattern {0}")]
+    PatternNotFound(String),
+    #[error("invalid pattern {0}: {1}")]
+    InvalidPattern(String, RenderError),
 }
 
 impl Default for Drawing {
@@ -43,6 +92,10 @@ impl Drawing {
             width,
             height,
             layer_order: vec![],
+            palette: Palette::new(),
+            brush_presets: vec![],
+            patterns: vec![],
+            symmetry: None,
         }
     }
 
@@ -100,11 +153,18 @@ impl Drawing {
     }
 
     /// Applies the given instruction to the given layer..
+    ///
+    /// Instructions depending on other layers capture them, unless they
+    /// already did (see [`Drawing::capture_sources`]).
     pub fn instruct(
         &mut self,
         layer_name: &str,
-        instruction: InstructionBox,
+        mut instruction: InstructionBox,
     ) -> Result<(), DrawingError> {
+        self.find_patterns(&instruction.instruction)?;
+        if !is_captured(&instruction.instruction) {
+            self.capture_sources(&mut instruction.instruction)?;
+        }
         let layer = self.layers.get_mut(layer_name);
         if let Some(l) = layer {
             l.instruct(instruction)?;
@@ -128,6 +188,56 @@ impl Drawing {
         }
     }
 
+    /// Captures what an instruction depending on other layers needs from
+    /// them, as they are now.
+    ///
+    /// Clone stamps with a source layer capture the pixels they copy and
+    /// bucket fills sampling every layer capture their region. Captures
+    /// already present are replaced, so only the ones made here are trusted.
+    /// Other instructions are left untouched.
+    pub fn capture_sources(&self, instruction: &mut Instruction) -> Result<(), DrawingError> {
+        match instruction {
+            Instruction::CloneStamp(clone) => {
+                clone.clear_source();
+                let Some(source_layer) = &clone.source_layer else {
+                    return Ok(());
+                };
+                let layer = self
+                    .layers
+                    .get(source_layer)
+                    .ok_or_else(|| DrawingError::LayerNotFound(source_layer.clone()))?;
+                let mut pixmap = layer.render(self.width, self.height, &self.patterns)?;
+                layer.apply_mask_to(&mut pixmap, &self.patterns)?;
+                clone.capture(&pixmap)?;
+            }
+            Instruction::Bucket(bucket) => {
+                bucket.clear_merged_region();
+                if bucket.sample() == BucketSample::Merged {
+                    bucket.capture(&self.render()?)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Replaces a text of the given layer with another text.
+    pub fn replace_instruction(
+        &mut self,
+        layer_name: &str,
+        index: u64,
+        instruction: Instruction,
+    ) -> Result<(), DrawingError> {
+        self.find_patterns(&instruction)?;
+        let layer = self.layers.get_mut(layer_name);
+        if let Some(l) = layer {
+            l.replace_instruction(index, instruction)?;
+            Ok(())
+        } else {
+            Err(DrawingError::LayerNotFound(layer_name.to_string()))
+        }
+    }
+
     /// Clears the given layer.
     ///
     /// The layer is cleared by removing the history. You cannot undo this.
@@ -168,6 +278,19 @@ impl Drawing {
         }
     }
 
+    /// Hides every applied instruction created by the given author, in all layers.
+    ///
+    /// Returns the layer and history index of every hidden instruction.
+    pub fn revert_author(&mut self, author: &str) -> Result<Vec<(String, u64)>, DrawingError> {
+        let mut reverted = vec![];
+        for (name, layer) in self.layers.iter_mut() {
+            for index in layer.revert_author(author)? {
+                reverted.push((name.clone(), index));
+            }
+        }
+        Ok(reverted)
+    }
+
     /// Saves the given image as a snapshot of the given history index for the given layer.
     pub fn snapshot(
         &mut self,
@@ -225,4 +348,494 @@ impl Drawing {
             Err(DrawingError::LayerNotFound(layer_name.to_string()))
         }
     }
+
+    /// Sets whether the given layer is clipped to the layer below it.
+    pub fn set_clip_to_below(
+        &mut self,
+        layer_name: &str,
+        clip_to_below: bool,
+    ) -> Result<(), DrawingError> {
+        self.layer_mut(layer_name)?.set_clip_to_below(clip_to_below);
+        Ok(())
+    }
+
+    /// Adds an empty mask to the given layer.
+    pub fn add_mask(&mut self, layer_name: &str) -> Result<(), DrawingError> {
+        self.layer_mut(layer_name)?.add_mask()?;
+        Ok(())
+    }
+
+    /// Removes the mask of the given layer, without changing the layer's pixels.
+    pub fn remove_mask(&mut self, layer_name: &str) -> Result<(), DrawingError> {
+        self.layer_mut(layer_name)?.remove_mask()?;
+        Ok(())
+    }
+
+    /// Applies the given instruction to the mask of the given layer.
+    pub fn mask_instruct(
+        &mut self,
+        layer_name: &str,
+        instruction: InstructionBox,
+    ) -> Result<(), DrawingError> {
+        self.find_patterns(&instruction.instruction)?;
+        self.layer_mut(layer_name)?
+            .mask_mut()?
+            .content_mut()
+            .instruct(instruction)?;
+        Ok(())
+    }
+
+    /// Set the history index of the mask of the given layer.
+    pub fn set_mask_history_index(
+        &mut self,
+        layer_name: &str,
+        new_history_index: u64,
+    ) -> Result<(), DrawingError> {
+        self.layer_mut(layer_name)?
+            .mask_mut()?
+            .content_mut()
+            .set_history_index(new_history_index)?;
+        Ok(())
+    }
+
+    /// Enables or disables the mask of the given layer.
+    pub fn set_mask_enabled(
+        &mut self,
+        layer_name: &str,
+        enabled: bool,
+    ) -> Result<(), DrawingError> {
+        self.layer_mut(layer_name)?.mask_mut()?.set_enabled(enabled);
+        Ok(())
+    }
+
+    /// Bakes the mask of the given layer into the layer, see [`Layer::apply_mask`].
+    pub fn apply_mask(
+        &mut self,
+        layer_name: &str,
+        uuid: String,
+    ) -> Result<&mut InstructionBox, DrawingError> {
+        Ok(self.layer_mut(layer_name)?.apply_mask(uuid)?)
+    }
+
+    fn layer_mut(&mut self, name: &str) -> Result<&mut Layer, DrawingError> {
+        self.layers
+            .get_mut(name)
+            .ok_or_else(|| DrawingError::LayerNotFound(name.to_string()))
+    }
+
+    /// Returns the palette shared by the users of the drawing.
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Replaces the whole palette, when importing one for example.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Adds a swatch at the end of the palette.
+    pub fn add_swatch(&mut self, swatch: Swatch) -> Result<(), DrawingError> {
+        self.palette.add(swatch)?;
+        Ok(())
+    }
+
+    /// Removes the swatch with the given name from the palette.
+    pub fn remove_swatch(&mut self, name: &str) -> Result<(), DrawingError> {
+        self.palette.remove(name)?;
+        Ok(())
+    }
+
+    /// Moves the swatch with the given name to the given position in the palette.
+    pub fn move_swatch(&mut self, name: &str, position: usize) -> Result<(), DrawingError> {
+        self.palette.move_swatch(name, position)?;
+        Ok(())
+    }
+
+    /// Returns the brush presets of the drawing, in the order they were created.
+    pub fn brush_presets(&self) -> &[BrushPreset] {
+        &self.brush_presets
+    }
+
+    /// Adds a brush preset.
+    ///
+    /// Since names are used as identifiers, this fails if another preset has
+    /// the same name.
+    pub fn add_brush_preset(&mut self, preset: BrushPreset) -> Result<(), DrawingError> {
+        preset.brush.validate()?;
+        if self.brush_presets.iter().any(|p| p.name == preset.name) {
+            return Err(DrawingError::BrushPresetAlreadyExists(preset.name));
+        }
+        self.brush_presets.push(preset);
+        Ok(())
+    }
+
+    /// Replaces the brush of the preset with the same name.
+    pub fn update_brush_preset(&mut self, preset: BrushPreset) -> Result<(), DrawingError> {
+        preset.brush.validate()?;
+        let existing = self
+            .brush_presets
+            .iter_mut()
+            .find(|p| p.name == preset.name)
+            .ok_or_else(|| DrawingError::BrushPresetNotFound(preset.name.clone()))?;
+        existing.brush = preset.brush;
+        Ok(())
+    }
+
+    /// Removes the brush preset with the given name.
+    pub fn remove_brush_preset(&mut self, name: &str) -> Result<(), DrawingError> {
+        let index = self
+            .brush_presets
+            .iter()
+            .position(|p| p.name == name)
+            .ok_or_else(|| DrawingError::BrushPresetNotFound(name.to_string()))?;
+        self.brush_presets.remove(index);
+        Ok(())
+    }
+
+    /// Returns the patterns of the drawing, in the order they were added.
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    /// Adds a pattern that bucket fills can repeat.
+    ///
+    /// Since ids are used as identifiers, this fails if another pattern has
+    /// the same id. Patterns cannot be removed, as fills in the history keep
+    /// using them.
+    pub fn add_pattern(&mut self, pattern: Pattern) -> Result<(), DrawingError> {
+        if let Err(e) = pattern.decode() {
+            return Err(DrawingError::InvalidPattern(pattern.id, e));
+        }
+        if self.patterns.iter().any(|p| p.id == pattern.id) {
+            return Err(DrawingError::PatternAlreadyExists(pattern.id));
+        }
+        self.patterns.push(pattern);
+        Ok(())
+    }
+
+    /// Checks that the patterns an instruction fills with exist.
+    fn find_patterns(&self, instruction: &Instruction) -> Result<(), DrawingError> {
+        match instruction {
+            Instruction::Bucket(bucket) => match bucket.fill() {
+                BucketFill::Pattern { pattern, .. }
+                    if !self.patterns.iter().any(|p| &p.id == pattern) =>
+                {
+                    Err(DrawingError::PatternNotFound(pattern.clone()))
+                }
+                _ => Ok(()),
+            },
+            Instruction::ApplyMask(mask) => {
+                mask.mask.iter().try_for_each(|i| self.find_patterns(i))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the symmetry shared by the users of the drawing, if any.
+    ///
+    /// The symmetry is a setting for clients, which draw
+    /// [`SymmetricStroke`](crate::SymmetricStroke)s with it. Changing it does
+    /// not change the strokes already drawn.
+    pub fn symmetry(&self) -> Option<&Symmetry> {
+        self.symmetry.as_ref()
+    }
+
+    /// Changes the symmetry of the drawing, or turns it off.
+    pub fn set_symmetry(&mut self, symmetry: Option<Symmetry>) -> Result<(), DrawingError> {
+        if let Some(symmetry) = &symmetry {
+            symmetry.validate()?;
+        }
+        self.symmetry = symmetry;
+        Ok(())
+    }
+
+    /// Returns the name of the layers, from the bottom one to the top one.
+    pub fn layer_order(&self) -> &[String] {
+        &self.layer_order
+    }
+
+    /// Returns the layer with the given name.
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.get(name)
+    }
+
+    /// Renders all the visible layers on top of each other.
+    pub fn render(&self) -> Result<Pixmap, DrawingError> {
+        let mut compositor = Compositor::new(self.width, self.height)?;
+        for layer in self
+            .layer_order
+            .iter()
+            .filter_map(|name| self.layers.get(name))
+        {
+            let rendered = if layer.is_visible() {
+                let mut rendered = layer.render(self.width, self.height, &self.patterns)?;
+                layer.apply_mask_to(&mut rendered, &self.patterns)?;
+                Some(Cow::Owned(rendered))
+            } else {
+                None
+            };
+            compositor.push(rendered, layer.clip_to_below());
+        }
+        Ok(compositor.finish())
+    }
+
+    /// Returns every applied instruction of the visible layers whose footprint
+    /// covers the given point, from the topmost to the bottommost.
+    pub fn instructions_at(&self, point: &Point) -> Result<Vec<InstructionHit>, DrawingError> {
+        let mut hits = vec![];
+        for name in self.layer_order.iter().rev() {
+            let Some(layer) = self.layers.get(name) else {
+                continue;
+            };
+            if !layer.is_visible() {
+                continue;
+            }
+            for (index, instruction) in
+                layer.instructions_at(point, self.width, self.height, &self.patterns)?
+            {
+                hits.push(InstructionHit {
+                    layer: name.clone(),
+                    index,
+                    uuid: instruction.uuid.clone(),
+                    author: instruction.author.clone(),
+                });
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Returns the applied instructions of every layer, in the order they were made.
+    ///
+    /// Instructions keep the order of their layer's history, and layers are
+    /// interleaved by creation time when it is known. Instructions without a
+    /// creation time are considered to be made at the same time as the
+    /// previous instruction of their layer.
+    pub fn construction_order(&self) -> Vec<(&str, &InstructionBox)> {
+        // Remaining history and creation time of the last instruction of every layer.
+        let mut cursors: Vec<(&str, &[InstructionBox], i64)> = self
+            .layer_order
+            .iter()
+            .filter_map(|name| {
+                let layer = self.layers.get(name)?;
+                let history = &layer.history()[..layer.history_index() as usize];
+                Some((name.as_str(), history, i64::MIN))
+            })
+            .collect();
+
+        let mut order = vec![];
+        loop {
+            let mut best: Option<(usize, i64)> = None;
+            for (cursor, (_, history, last)) in cursors.iter().enumerate() {
+                let Some(instruction) = history.first() else {
+                    continue;
+                };
+                let time = instruction.created_at.unwrap_or(*last);
+                if best.is_none_or(|(_, best_time)| time < best_time) {
+                    best = Some((cursor, time));
+                }
+            }
+            let Some((cursor, time)) = best else {
+                break;
+            };
+            let (name, history, last) = &mut cursors[cursor];
+            let (instruction, rest) = history.split_first().unwrap();
+            if instruction.applied {
+                order.push((*name, instruction));
+            }
+            *history = rest;
+            *last = time;
+        }
+        order
+    }
+
+    /// Replays the construction of the drawing, yielding one frame per applied instruction.
+    pub fn timelapse(&self) -> Result<Timelapse<'_>, DrawingError> {
+        Ok(Timelapse::new(self)?)
+    }
+}
+
+/// Returns false if the instruction depends on other layers and did not capture them yet.
+fn is_captured(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::CloneStamp(clone) => clone.source_layer.is_none() || clone.source().is_some(),
+        Instruction::Bucket(bucket) => {
+            bucket.sample() == BucketSample::Layer || bucket.merged_region().is_some()
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_skia::ColorU8;
+
+    use super::*;
+    use crate::{
+        render::{canvas, encode_image},
+        Brush, Bucket, Stroke,
+    };
+
+    fn stroke(uuid: &str, author: &str) -> InstructionBox {
+        let brush = Brush {
+            width: 4.0,
+            opacity: u32::MAX,
+            ..Default::default()
+        };
+        InstructionBox {
+            instruction: Instruction::Stroke(Stroke::new(
+                vec![Point::new(5.0, 2.0), Point::new(5.0, 18.0)],
+                brush,
+            )),
+            applied: true,
+            uuid: uuid.to_string(),
+            author: Some(author.to_string()),
+            created_at: None,
+        }
+    }
+
+    /// Returns the layer and uuid of the instructions at the given point.
+    fn hits(drawing: &Drawing, x: f32, y: f32) -> Vec<(String, String)> {
+        drawing
+            .instructions_at(&Point::new(x, y))
+            .unwrap()
+            .into_iter()
+            .map(|hit| (hit.layer, hit.uuid))
+            .collect()
+    }
+
+    #[test]
+    fn instructions_are_hit_from_the_topmost_visible_layer() {
+        let mut drawing = Drawing::new(20, 20);
+        drawing.add_layer("bottom".into()).unwrap();
+        drawing.add_layer("top".into()).unwrap();
+        drawing.instruct("bottom", stroke("a", "alice")).unwrap();
+        drawing.instruct("top", stroke("b", "bob")).unwrap();
+        drawing.instruct("bottom", stroke("c", "alice")).unwrap();
+
+        let expected = [("top", "b"), ("bottom", "c"), ("bottom", "a")];
+        assert_eq!(
+            hits(&drawing, 5.0, 10.0),
+            expected.map(|(layer, uuid)| (layer.to_string(), uuid.to_string()))
+        );
+        let hit = &drawing.instructions_at(&Point::new(5.0, 10.0)).unwrap()[0];
+        assert_eq!((hit.index, hit.author.as_deref()), (1, Some("bob")));
+
+        drawing.set_visibility("top", false).unwrap();
+        assert_eq!(
+            hits(&drawing, 5.0, 10.0),
+            [("bottom", "c"), ("bottom", "a")].map(|(l, u)| (l.to_string(), u.to_string()))
+        );
+        assert!(hits(&drawing, 15.0, 10.0).is_empty());
+    }
+
+    /// Returns the applied instructions of every layer.
+    fn applied(drawing: &Drawing) -> Vec<(String, u64)> {
+        let mut applied: Vec<(String, u64)> = drawing
+            .layers
+            .iter()
+            .flat_map(|(name, layer)| {
+                layer
+                    .history()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, i)| i.applied)
+                    .map(move |(index, _)| (name.clone(), index as u64 + 1))
+            })
+            .collect();
+        applied.sort();
+        applied
+    }
+
+    #[test]
+    fn reverting_an_author_hides_only_their_applied_instructions() {
+        let mut drawing = Drawing::new(20, 20);
+        drawing.add_layer("bottom".into()).unwrap();
+        drawing.add_layer("top".into()).unwrap();
+        drawing.instruct("bottom", stroke("a", "alice")).unwrap();
+        drawing.instruct("bottom", stroke("b", "bob")).unwrap();
+        drawing.instruct("bottom", stroke("c", "alice")).unwrap();
+        drawing.instruct("top", stroke("d", "alice")).unwrap();
+        drawing.instruct("top", stroke("e", "alicia")).unwrap();
+        drawing
+            .set_instruction_visibility("bottom", 3, false)
+            .unwrap();
+        let before = applied(&drawing);
+
+        let mut reverted = drawing.revert_author("alice").unwrap();
+        reverted.sort();
+        assert_eq!(reverted, [("bottom".into(), 1), ("top".into(), 1)]);
+        // The returned list is exactly what changed, so it can be broadcast as is.
+        let after = applied(&drawing);
+        let hidden: Vec<_> = before.into_iter().filter(|i| !after.contains(i)).collect();
+        assert_eq!(hidden, reverted);
+        assert_eq!(after, [("bottom".into(), 2), ("top".into(), 2)]);
+
+        assert!(drawing.revert_author("alice").unwrap().is_empty());
+        assert!(drawing.revert_author("nobody").unwrap().is_empty());
+    }
+
+    /// Returns a pattern of vertical stripes, one opaque column out of two.
+    fn stripes(id: &str) -> Pattern {
+        let mut pixmap = canvas(2, 1).unwrap();
+        pixmap.pixels_mut()[0] = ColorU8::from_rgba(0, 0, 0, 255).premultiply();
+        Pattern::new(id, encode_image(&pixmap).unwrap())
+    }
+
+    fn pattern_fill(pattern: &str, scale: f32) -> InstructionBox {
+        let brush = Brush {
+            opacity: u32::MAX,
+            ..Default::default()
+        };
+        let mut bucket = Bucket::new(Point::new(0.0, 0.0), brush, 0);
+        bucket.set_fill(BucketFill::Pattern {
+            pattern: pattern.to_string(),
+            offset: Point::new(0.0, 0.0),
+            scale,
+        });
+        InstructionBox {
+            instruction: Instruction::Bucket(bucket),
+            applied: true,
+            uuid: "fill".to_string(),
+            author: None,
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn pattern_fills_use_the_patterns_of_the_drawing() {
+        let mut drawing = Drawing::new(4, 4);
+        drawing.add_layer("layer".into()).unwrap();
+        assert!(matches!(
+            drawing.instruct("layer", pattern_fill("stripes", 1.0)),
+            Err(DrawingError::PatternNotFound(_))
+        ));
+
+        drawing.add_pattern(stripes("stripes")).unwrap();
+        assert!(matches!(
+            drawing.add_pattern(stripes("stripes")),
+            Err(DrawingError::PatternAlreadyExists(_))
+        ));
+        let broken = Pattern::new("broken", "not an image".to_string());
+        assert!(matches!(
+            drawing.add_pattern(broken),
+            Err(DrawingError::InvalidPattern(..))
+        ));
+        for scale in [0.0, -1.0, f32::INFINITY, f32::NAN] {
+            assert!(matches!(
+                drawing.instruct("layer", pattern_fill("stripes", scale)),
+                Err(DrawingError::LayerError(LayerError::InvalidPatternScale(_)))
+            ));
+        }
+
+        drawing
+            .instruct("layer", pattern_fill("stripes", 1.0))
+            .unwrap();
+        let rendered = drawing.render().unwrap();
+        let alphas: Vec<u8> = rendered.pixels()[..4].iter().map(|p| p.alpha()).collect();
+        assert_eq!(alphas, [255, 0, 255, 0]);
+
+        // The image is stored once, on the drawing, and not in the fill.
+        let json = serde_json::to_string(&drawing).unwrap();
+        assert_eq!(json.matches(&drawing.patterns()[0].image).count(), 1);
+    }
 }
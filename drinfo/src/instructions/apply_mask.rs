@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::Pixmap;
+
+use crate::{
+    render::{canvas, grayscale_mask, pixel_index, RenderError},
+    Instruction, Pattern, Point,
+};
+
+/// An instruction baking a layer mask into the pixels of the layer.
+///
+/// See [`LayerMask`](crate::LayerMask) for how the mask is interpreted.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MaskApplication {
+    /// The instructions that drew the mask, in order.
+    pub mask: Vec<Instruction>,
+}
+
+impl MaskApplication {
+    pub fn new(mask: Vec<Instruction>) -> Self {
+        MaskApplication { mask }
+    }
+
+    fn render_mask(
+        &self,
+        width: u32,
+        height: u32,
+        patterns: &[Pattern],
+    ) -> Result<tiny_skia::Mask, RenderError> {
+        let mut mask = canvas(width, height)?;
+        for instruction in &self.mask {
+            instruction.render(&mut mask, patterns)?;
+        }
+        grayscale_mask(&mask)
+    }
+
+    /// Makes the hidden parts of the given canvas transparent.
+    pub fn render(&self, pixmap: &mut Pixmap, patterns: &[Pattern]) -> Result<(), RenderError> {
+        let mask = self.render_mask(pixmap.width(), pixmap.height(), patterns)?;
+        pixmap.apply_mask(&mask);
+        Ok(())
+    }
+
+    /// Returns true if the mask hides the given point, even partially.
+    pub fn covers(
+        &self,
+        point: &Point,
+        pixmap: &Pixmap,
+        patterns: &[Pattern],
+    ) -> Result<bool, RenderError> {
+        let Some(index) = pixel_index(pixmap, point) else {
+            return Ok(false);
+        };
+        let mask = self.render_mask(pixmap.width(), pixmap.height(), patterns)?;
+        Ok(mask.data()[index] < u8::MAX)
+    }
+}
@@ -1,16 +1,375 @@
 use serde::{Deserialize, Serialize};
+use tiny_skia::{ColorU8, FilterQuality, Paint, Pixmap, Rect, Shader, SpreadMode, Transform};
 
-use crate::{Brush, Point};
+use crate::{
+    instructions::gradient::gradient_shader,
+    pattern,
+    render::{
+        canvas, decode_image, encode_image, fill_region, pixel_index, premultiplied, ratio,
+        RegionOptions, RenderError,
+    },
+    Brush, ColorStop, GradientShape, Pattern, Point, Selection,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Bucket {
     point: Point,
     brush: Brush,
     tolerance: u32,
+    /// The pixels the region is computed from.
+    #[serde(default)]
+    sample: BucketSample,
+    /// Whether every similar pixel is filled, connected to the point or not.
+    #[serde(default)]
+    global: bool,
+    /// How many more pixels the region is grown by.
+    #[serde(default)]
+    expand: u32,
+    /// The width of the gaps in line art that the fill does not leak through.
+    #[serde(default)]
+    gap_closing: u32,
+    /// The region computed from every visible layer, captured when the fill
+    /// is added to the [`Drawing`](crate::Drawing).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    merged_region: Option<MergedRegion>,
+    /// What the region is filled with.
+    #[serde(default)]
+    fill: BucketFill,
+    /// The selection the fill is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clip: Option<Selection>,
 }
 
+/// The region of a [`Bucket`] computed from every visible layer.
+///
+/// Only the bounding box of the filled pixels is kept.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MergedRegion {
+    /// The position of the top left pixel of the image on the canvas.
+    pub x: u32,
+    pub y: u32,
+    /// The filled pixels, opaque in a PNG data URL.
+    pub image: String,
+}
+
+/// What a [`Bucket`] fills its region with.
+///
+/// Filled pixels are replaced, with the opacity of the brush.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub enum BucketFill {
+    /// The color of the brush.
+    #[default]
+    Color,
+    /// A gradient, placed on the canvas like the gradient of a
+    /// [`Gradient`](crate::Gradient) instruction.
+    Gradient {
+        shape: GradientShape,
+        /// The colors of the gradient, at least one.
+        stops: Vec<ColorStop>,
+    },
+    /// An image repeated over the canvas, like a screentone.
+    Pattern {
+        /// The id of the [`Pattern`] of the drawing to repeat.
+        pattern: String,
+        /// Where the top left corner of one of the tiles is.
+        #[serde(default)]
+        offset: Point,
+        /// The size of the tiles relative to the size of the image, above 0.
+        #[serde(default = "one")]
+        scale: f32,
+    },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+/// The pixels a [`Bucket`] fill region is computed from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BucketSample {
+    /// The pixels of the layer being filled.
+    #[default]
+    Layer,
+    /// The pixels of every visible layer, as they look together.
+    ///
+    /// The region is computed when the fill is added to the drawing, later
+    /// changes to the other layers do not change it. Without a drawing, the
+    /// layer being filled is used.
+    Merged,
+}
+
+/// The largest number of pixels a bucket fill can be expanded by, or close gaps of.
+pub const MAX_BUCKET_GROWTH: u32 = 64;
+
 impl Bucket {
     pub fn new(point: Point, brush: Brush, tolerance: u32) -> Self {
-        Bucket { point, brush, tolerance }
+        Bucket {
+            point,
+            brush,
+            tolerance,
+            sample: BucketSample::Layer,
+            global: false,
+            expand: 0,
+            gap_closing: 0,
+            merged_region: None,
+            fill: BucketFill::Color,
+            clip: None,
+        }
+    }
+
+    /// Gets the point where the fill starts.
+    pub fn point(&self) -> &Point {
+        &self.point
+    }
+
+    /// Gets the brush used to fill.
+    pub fn brush(&self) -> &Brush {
+        &self.brush
+    }
+
+    /// Gets the color tolerance of the fill.
+    ///
+    /// [`u32::MAX`] is 1 and 0 is 0.
+    pub fn tolerance(&self) -> u32 {
+        self.tolerance
+    }
+
+    /// Gets the pixels the region is computed from.
+    pub fn sample(&self) -> BucketSample {
+        self.sample
+    }
+
+    /// Changes the pixels the region is computed from.
+    pub fn set_sample(&mut self, sample: BucketSample) {
+        self.sample = sample;
+        self.merged_region = None;
+    }
+
+    /// Returns true if every similar pixel is filled, connected to the point or not.
+    pub fn global(&self) -> bool {
+        self.global
+    }
+
+    pub fn set_global(&mut self, global: bool) {
+        self.global = global;
+    }
+
+    /// Gets how many more pixels the region is grown by, to avoid halos
+    /// around anti-aliased lines.
+    pub fn expand(&self) -> u32 {
+        self.expand
+    }
+
+    pub fn set_expand(&mut self, expand: u32) {
+        self.expand = expand;
+    }
+
+    /// Gets the width of the gaps in line art that the fill does not leak through.
+    pub fn gap_closing(&self) -> u32 {
+        self.gap_closing
+    }
+
+    pub fn set_gap_closing(&mut self, gap_closing: u32) {
+        self.gap_closing = gap_closing;
+    }
+
+    /// Gets what the region is filled with.
+    pub fn fill(&self) -> &BucketFill {
+        &self.fill
+    }
+
+    pub fn set_fill(&mut self, fill: BucketFill) {
+        self.fill = fill;
+    }
+
+    /// Gets the selection the fill is clipped to, if any.
+    pub fn clip(&self) -> Option<&Selection> {
+        self.clip.as_ref()
+    }
+
+    pub fn set_clip(&mut self, clip: Option<Selection>) {
+        self.clip = clip;
+    }
+
+    /// Gets the region computed from every visible layer, if it was captured.
+    pub fn merged_region(&self) -> Option<&MergedRegion> {
+        self.merged_region.as_ref()
+    }
+
+    /// Computes which pixels of the canvas are filled.
+    ///
+    /// The region is made of every pixel connected to the start point (or of
+    /// every pixel when the fill is global) whose channels all differ by at
+    /// most `tolerance` from the start pixel, grown by one pixel to cover
+    /// anti-aliased edges, plus `expand` pixels.
+    ///
+    /// When sampling every layer, the captured merged region is used instead
+    /// of the canvas.
+    pub fn region(&self, pixmap: &Pixmap) -> Vec<bool> {
+        if let Some((merged, image)) = self
+            .merged_region
+            .as_ref()
+            .filter(|_| self.sample == BucketSample::Merged)
+            .and_then(|r| Some((r, decode_image(&r.image).ok()?)))
+        {
+            let (width, height) = (pixmap.width() as usize, pixmap.height() as usize);
+            let mut region = vec![false; width * height];
+            for (i, pixel) in image.pixels().iter().enumerate() {
+                let x = merged.x as usize + i % image.width() as usize;
+                let y = merged.y as usize + i / image.width() as usize;
+                if x < width && y < height && pixel.alpha() > 0 {
+                    region[y * width + x] = true;
+                }
+            }
+            return region;
+        }
+        fill_region(
+            pixmap,
+            &self.point,
+            &RegionOptions {
+                tolerance: self.tolerance,
+                global: self.global,
+                expand: self.expand,
+                gap_closing: self.gap_closing,
+            },
+        )
+    }
+
+    /// Forgets the captured merged region.
+    pub(crate) fn clear_merged_region(&mut self) {
+        self.merged_region = None;
+    }
+
+    /// Captures the region computed from every visible layer, rendered together.
+    pub(crate) fn capture(&mut self, merged: &Pixmap) -> Result<(), RenderError> {
+        self.clear_merged_region();
+        let region = self.region(merged);
+        let width = merged.width() as usize;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+        for (i, _) in region.iter().enumerate().filter(|(_, filled)| **filled) {
+            let (x, y) = (i % width, i / width);
+            (min_x, min_y) = (min_x.min(x), min_y.min(y));
+            (max_x, max_y) = (max_x.max(x), max_y.max(y));
+        }
+        if min_x > max_x {
+            // Nothing is filled, a single transparent pixel is enough.
+            (min_x, min_y, max_x, max_y) = (0, 0, 0, 0);
+        }
+        let mut pixmap = canvas((max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32)?;
+        let image_width = pixmap.width() as usize;
+        for (i, pixel) in pixmap.pixels_mut().iter_mut().enumerate() {
+            let (x, y) = (min_x + i % image_width, min_y + i / image_width);
+            if region.get(y * width + x).copied().unwrap_or(false) {
+                *pixel = ColorU8::from_rgba(0, 0, 0, 255).premultiply();
+            }
+        }
+        self.merged_region = Some(MergedRegion {
+            x: min_x as u32,
+            y: min_y as u32,
+            image: encode_image(&pixmap)?,
+        });
+        Ok(())
+    }
+
+    /// Fills the region on the given canvas.
+    ///
+    /// Pattern fills look their image up in the given patterns of the drawing.
+    pub fn render(&self, pixmap: &mut Pixmap, patterns: &[Pattern]) -> Result<(), RenderError> {
+        let region = self.region(pixmap);
+        let opacity = ratio(self.brush.opacity);
+        let (width, height) = (pixmap.width(), pixmap.height());
+        let filled = match &self.fill {
+            BucketFill::Color => {
+                let color = premultiplied(&self.brush.color, self.brush.opacity);
+                for (pixel, _) in pixmap
+                    .pixels_mut()
+                    .iter_mut()
+                    .zip(region)
+                    .filter(|(_, filled)| *filled)
+                {
+                    *pixel = color;
+                }
+                return Ok(());
+            }
+            BucketFill::Gradient { shape, stops } => {
+                let Some(shader) = gradient_shader(shape, stops, opacity) else {
+                    return Ok(());
+                };
+                fill_canvas(width, height, shader)?
+            }
+            BucketFill::Pattern {
+                pattern,
+                offset,
+                scale,
+            } => {
+                let image = pattern::find(patterns, pattern)?.decode()?;
+                let shader = tiny_skia::Pattern::new(
+                    image.as_ref(),
+                    SpreadMode::Repeat,
+                    FilterQuality::Bilinear,
+                    opacity,
+                    Transform::from_translate(offset.x, offset.y).pre_scale(*scale, *scale),
+                );
+                fill_canvas(width, height, shader)?
+            }
+        };
+        for ((pixel, fill), _) in pixmap
+            .pixels_mut()
+            .iter_mut()
+            .zip(filled.pixels())
+            .zip(region)
+            .filter(|(_, filled)| *filled)
+        {
+            *pixel = *fill;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the fill covers the given point when applied on the given canvas.
+    pub fn covers(&self, point: &Point, pixmap: &Pixmap) -> bool {
+        pixel_index(pixmap, point).is_some_and(|index| self.region(pixmap)[index])
+    }
+}
+
+/// Paints a whole canvas with the given shader.
+fn fill_canvas(width: u32, height: u32, shader: Shader) -> Result<Pixmap, RenderError> {
+    let mut pixmap = canvas(width, height)?;
+    let paint = Paint {
+        shader,
+        ..Default::default()
+    };
+    if let Some(rect) = Rect::from_xywh(0.0, 0.0, width as f32, height as f32) {
+        pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+    }
+    Ok(pixmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captured_region_matches_the_canvas() {
+        let mut merged = canvas(30, 20).unwrap();
+        merged.fill(tiny_skia::Color::WHITE);
+        for y in 0..20 {
+            for x in [5, 20] {
+                merged.pixels_mut()[y * 30 + x] = ColorU8::from_rgba(0, 0, 0, 255).premultiply();
+            }
+        }
+        for point in [
+            Point::new(10.0, 10.0),
+            Point::new(25.0, 3.0),
+            Point::new(-4.0, 3.0),
+        ] {
+            let mut bucket = Bucket::new(point, Brush::default(), 0);
+            bucket.set_sample(BucketSample::Merged);
+            let region = bucket.region(&merged);
+            bucket.capture(&merged).unwrap();
+            let captured = bucket.merged_region().unwrap();
+            assert!(decode_image(&captured.image).unwrap().width() < 30);
+            // The captured region is used even on a different canvas.
+            assert_eq!(bucket.region(&canvas(30, 20).unwrap()), region);
+        }
     }
 }
@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::{ColorU8, Pixmap, PremultipliedColorU8};
+
+use crate::{
+    color::{hsl_to_rgb, rgb_to_hsl},
+    render::{degrees, pixel_index, RenderError},
+    Point, Selection,
+};
+
+/// A filter instruction, adjusting the pixels already drawn on the layer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Filter {
+    /// How pixels are changed.
+    pub effect: FilterEffect,
+    /// The selection the filter is clipped to.
+    ///
+    /// The whole layer is filtered when there is no selection.
+    #[serde(default)]
+    pub selection: Option<Selection>,
+}
+
+/// The largest radius of a blur, in pixels.
+pub const MAX_BLUR_RADIUS: f32 = 256.0;
+
+/// The effect of a filter.
+///
+/// Color adjustments work on channels between 0 and 1, without premultiplied
+/// alpha, and leave the alpha channel untouched.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FilterEffect {
+    /// Gaussian blur with a standard deviation of `radius / 2` pixels.
+    ///
+    /// Transparency is blurred too, pixels outside of the canvas are transparent.
+    Blur { radius: f32 },
+    /// Unsharp mask: adds `amount` times the difference between the pixels
+    /// and their blur of the given radius.
+    Sharpen { radius: f32, amount: f32 },
+    /// `brightness` (from -1 to 1) is added to every channel, after the
+    /// channels are scaled around 0.5 by `tan((contrast + 1) * PI / 4)`,
+    /// with `contrast` from -1 to 1.
+    BrightnessContrast { brightness: f32, contrast: f32 },
+    /// Works in the HSL color space.
+    ///
+    /// The hue is rotated by `hue / u32::MAX * 360` degrees, the saturation is
+    /// multiplied by `1 + saturation` and the lightness is moved towards white
+    /// (positive) or black (negative) by `lightness`, both from -1 to 1.
+    HueSaturation {
+        hue: u32,
+        saturation: f32,
+        lightness: f32,
+    },
+    /// Every channel becomes `1 - channel`.
+    Invert,
+    /// Channels between `input_black` and `input_white` are stretched to
+    /// `0..1`, raised to the power of `1 / gamma`, then mapped to
+    /// `output_black..output_white`.
+    Levels {
+        input_black: u8,
+        input_white: u8,
+        gamma: f32,
+        output_black: u8,
+        output_white: u8,
+    },
+}
+
+impl Filter {
+    pub fn new(effect: FilterEffect, selection: Option<Selection>) -> Self {
+        Filter { effect, selection }
+    }
+
+    /// Filters the pixels of the given canvas, ignoring the selection.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let filtered = match &self.effect {
+            FilterEffect::Blur { radius } => blur(pixmap, *radius),
+            FilterEffect::Sharpen { radius, amount } => {
+                let blurred = blur(pixmap, *radius);
+                pixmap
+                    .pixels()
+                    .iter()
+                    .zip(blurred)
+                    .map(|(pixel, blurred)| {
+                        let pixel = channels(*pixel);
+                        let alpha = pixel[3];
+                        let mut sharpened = pixel;
+                        for c in 0..3 {
+                            sharpened[c] =
+                                (pixel[c] + amount * (pixel[c] - blurred[c])).clamp(0.0, alpha);
+                        }
+                        sharpened
+                    })
+                    .collect()
+            }
+            FilterEffect::BrightnessContrast {
+                brightness,
+                contrast,
+            } => {
+                let factor =
+                    ((contrast.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4).tan();
+                adjust(pixmap, |rgb| {
+                    rgb.map(|c| (c - 0.5) * factor + 0.5 + brightness)
+                })
+            }
+            FilterEffect::HueSaturation {
+                hue,
+                saturation,
+                lightness,
+            } => adjust(pixmap, |rgb| {
+                let (h, s, l) = rgb_to_hsl(rgb);
+                let s = s * (1.0 + saturation.clamp(-1.0, 1.0));
+                let l = if *lightness > 0.0 {
+                    l + (1.0 - l) * lightness.min(1.0)
+                } else {
+                    l * (1.0 + lightness.max(-1.0))
+                };
+                hsl_to_rgb((h + degrees(*hue)) % 360.0, s.clamp(0.0, 1.0), l)
+            }),
+            FilterEffect::Invert => adjust(pixmap, |rgb| rgb.map(|c| 1.0 - c)),
+            FilterEffect::Levels {
+                input_black,
+                input_white,
+                gamma,
+                output_black,
+                output_white,
+            } => {
+                let (input_black, input_white) =
+                    (*input_black as f32 / 255.0, *input_white as f32 / 255.0);
+                let (output_black, output_white) =
+                    (*output_black as f32 / 255.0, *output_white as f32 / 255.0);
+                let range = (input_white - input_black).max(1.0 / 255.0);
+                let exponent = 1.0 / gamma.max(0.01);
+                adjust(pixmap, |rgb| {
+                    rgb.map(|c| {
+                        let c = ((c - input_black) / range).clamp(0.0, 1.0);
+                        output_black + (output_white - output_black) * c.powf(exponent)
+                    })
+                })
+            }
+        };
+
+        for (pixel, filtered) in pixmap.pixels_mut().iter_mut().zip(filtered) {
+            *pixel = premultiplied_pixel(filtered);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the filter changes the given point of the given canvas,
+    /// ignoring the selection.
+    pub fn covers(&self, point: &Point, pixmap: &Pixmap) -> bool {
+        pixel_index(pixmap, point).is_some()
+    }
+}
+
+/// Converts a pixel to premultiplied channels between 0 and 1.
+pub(crate) fn channels(pixel: PremultipliedColorU8) -> [f32; 4] {
+    [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()].map(|c| c as f32 / 255.0)
+}
+
+/// Converts premultiplied channels between 0 and 1 to a pixel.
+pub(crate) fn premultiplied_pixel(channels: [f32; 4]) -> PremultipliedColorU8 {
+    let alpha = (channels[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+    let [r, g, b] = [channels[0], channels[1], channels[2]]
+        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round().min(alpha as f32) as u8);
+    PremultipliedColorU8::from_rgba(r, g, b, alpha).unwrap_or(PremultipliedColorU8::TRANSPARENT)
+}
+
+/// Applies a color adjustment to every pixel, returning premultiplied channels.
+fn adjust(pixmap: &Pixmap, adjustment: impl Fn([f32; 3]) -> [f32; 3]) -> Vec<[f32; 4]> {
+    pixmap
+        .pixels()
+        .iter()
+        .map(|pixel| {
+            let color: ColorU8 = pixel.demultiply();
+            let alpha = color.alpha() as f32 / 255.0;
+            let rgb =
+                adjustment([color.red(), color.green(), color.blue()].map(|c| c as f32 / 255.0))
+                    .map(|c| c.clamp(0.0, 1.0) * alpha);
+            [rgb[0], rgb[1], rgb[2], alpha]
+        })
+        .collect()
+}
+
+/// Blurs the canvas, returning premultiplied channels.
+pub(crate) fn blur(pixmap: &Pixmap, radius: f32) -> Vec<[f32; 4]> {
+    let pixels: Vec<[f32; 4]> = pixmap.pixels().iter().map(|p| channels(*p)).collect();
+    let sigma = radius / 2.0;
+    if sigma <= 0.0 {
+        return pixels;
+    }
+    let reach = radius.ceil() as isize;
+    let kernel: Vec<f32> = (-reach..=reach)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.into_iter().map(|k| k / total).collect();
+
+    let (width, height) = (pixmap.width() as isize, pixmap.height() as isize);
+    let pass = |source: &[[f32; 4]], horizontal: bool| -> Vec<[f32; 4]> {
+        let mut result = vec![[0.0; 4]; source.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 4];
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as isize - reach;
+                    let (sx, sy) = if horizontal {
+                        (x + offset, y)
+                    } else {
+                        (x, y + offset)
+                    };
+                    if sx < 0 || sy < 0 || sx >= width || sy >= height {
+                        continue;
+                    }
+                    let pixel = source[(sy * width + sx) as usize];
+                    for c in 0..4 {
+                        sum[c] += pixel[c] * weight;
+                    }
+                }
+                result[(y * width + x) as usize] = sum;
+            }
+        }
+        result
+    };
+    pass(&pass(&pixels, true), false)
+}
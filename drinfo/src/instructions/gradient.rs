@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::{
+    IntSize, LinearGradient, Mask, Paint, Pixmap, RadialGradient, Rect, Shader, SpreadMode,
+    Transform,
+};
+
+use crate::{
+    render::{flood_region, pixel_index, ratio, skia_color, RenderError},
+    Color, Point, Selection,
+};
+
+/// A gradient fill instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Gradient {
+    /// How colors are spread over the canvas.
+    pub shape: GradientShape,
+    /// The colors of the gradient, at least one.
+    pub stops: Vec<ColorStop>,
+    /// The part of the canvas to fill.
+    pub area: FillArea,
+    /// The selection the gradient is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
+}
+
+/// The geometry of a gradient.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum GradientShape {
+    /// Colors change along the line going from `start` (position 0) to `end` (position 1).
+    Linear { start: Point, end: Point },
+    /// Colors change from `center` (position 0) to the circle of the given radius (position 1).
+    Radial { center: Point, radius: f32 },
+}
+
+/// A color of a gradient.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ColorStop {
+    /// Where the color is on the gradient. [`u32::MAX`] is 1 and 0 is 0.
+    pub position: u32,
+    pub color: Color,
+    /// [`u32::MAX`] is 1 and 0 is 0.
+    pub opacity: u32,
+}
+
+/// The part of a canvas filled by an instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FillArea {
+    /// The whole layer.
+    Layer,
+    /// The inside of a selection.
+    ///
+    /// Polygons written as `Polygon` are still read.
+    #[serde(alias = "Polygon")]
+    Selection(Selection),
+    /// The contiguous region around a point, computed like [`Bucket`](crate::Bucket) does.
+    Region { point: Point, tolerance: u32 },
+}
+
+impl Gradient {
+    pub fn new(shape: GradientShape, stops: Vec<ColorStop>, area: FillArea) -> Self {
+        Gradient {
+            shape,
+            stops,
+            area,
+            clip: None,
+        }
+    }
+
+    /// Paints the gradient over the area on the given canvas.
+    ///
+    /// Transparent parts of the gradient let the existing pixels show through.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let Some(shader) = gradient_shader(&self.shape, &self.stops, 1.0) else {
+            return Ok(());
+        };
+        let paint = Paint {
+            shader,
+            anti_alias: true,
+            ..Default::default()
+        };
+        match &self.area {
+            FillArea::Layer => {
+                if let Some(rect) =
+                    Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32)
+                {
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                }
+            }
+            FillArea::Selection(selection) => {
+                let mask = selection.mask(pixmap.width(), pixmap.height())?;
+                let rect = Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32);
+                if let Some(rect) = rect {
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), Some(&mask));
+                }
+            }
+            FillArea::Region { point, tolerance } => {
+                let region = flood_region(pixmap, point, *tolerance);
+                let mask = IntSize::from_wh(pixmap.width(), pixmap.height()).and_then(|size| {
+                    Mask::from_vec(
+                        region
+                            .into_iter()
+                            .map(|filled| filled as u8 * 255)
+                            .collect(),
+                        size,
+                    )
+                });
+                let rect = Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32);
+                if let (Some(mask), Some(rect)) = (mask, rect) {
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), Some(&mask));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the gradient covers the given point when applied on the given canvas.
+    pub fn covers(&self, point: &Point, pixmap: &Pixmap) -> bool {
+        match &self.area {
+            FillArea::Layer => pixel_index(pixmap, point).is_some(),
+            FillArea::Selection(selection) => selection.contains(point),
+            FillArea::Region {
+                point: start,
+                tolerance,
+            } => pixel_index(pixmap, point)
+                .is_some_and(|index| flood_region(pixmap, start, *tolerance)[index]),
+        }
+    }
+}
+
+/// Builds the shader painting the given gradient, with its opacity
+/// multiplied by `opacity`.
+pub(crate) fn gradient_shader(
+    shape: &GradientShape,
+    stops: &[ColorStop],
+    opacity: f32,
+) -> Option<Shader<'static>> {
+    let mut sorted: Vec<_> = stops.iter().collect();
+    // Stable sort, stops at the same position keep their order.
+    sorted.sort_by_key(|stop| stop.position);
+    let stops = sorted
+        .into_iter()
+        .map(|stop| {
+            let mut color = skia_color(&stop.color, stop.opacity);
+            color.apply_opacity(opacity);
+            tiny_skia::GradientStop::new(ratio(stop.position), color)
+        })
+        .collect();
+    match shape {
+        GradientShape::Linear { start, end } => LinearGradient::new(
+            tiny_skia::Point::from_xy(start.x, start.y),
+            tiny_skia::Point::from_xy(end.x, end.y),
+            stops,
+            SpreadMode::Pad,
+            Transform::identity(),
+        ),
+        GradientShape::Radial { center, radius } => {
+            let center = tiny_skia::Point::from_xy(center.x, center.y);
+            RadialGradient::new(
+                center,
+                center,
+                *radius,
+                stops,
+                SpreadMode::Pad,
+                Transform::identity(),
+            )
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
+use tiny_skia::{FilterQuality, Pixmap, PixmapPaint, Transform};
 
-use crate::Point;
+use crate::{
+    render::{decode_image, degrees, RenderError},
+    Point, Selection,
+};
 
 /// An image insertion instruction.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -13,6 +17,9 @@ pub struct ImageInsertion {
     scale: Point,
     /// Rotation of the picture. `rotate / u32::MAX * 360` = rotation in degrees
     rotate: u32,
+    /// The selection the image is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clip: Option<Selection>,
 }
 
 impl ImageInsertion {
@@ -22,6 +29,75 @@ impl ImageInsertion {
             point,
             scale,
             rotate,
+            clip: None,
         }
     }
+
+    /// Gets the base64 representation of the image.
+    pub fn base64(&self) -> &str {
+        &self.base64
+    }
+
+    /// Gets the coordinates where the image is inserted.
+    pub fn point(&self) -> &Point {
+        &self.point
+    }
+
+    /// Gets the X and Y scale of the image.
+    pub fn scale(&self) -> &Point {
+        &self.scale
+    }
+
+    /// Gets the rotation of the image.
+    pub fn rotate(&self) -> u32 {
+        self.rotate
+    }
+
+    /// Gets the selection the image is clipped to, if any.
+    pub fn clip(&self) -> Option<&Selection> {
+        self.clip.as_ref()
+    }
+
+    pub fn set_clip(&mut self, clip: Option<Selection>) {
+        self.clip = clip;
+    }
+
+    /// Maps image pixels to canvas pixels.
+    ///
+    /// The image is scaled, then rotated around its center.
+    fn transform(&self, image: &Pixmap) -> Transform {
+        let width = image.width() as f32 * self.scale.x;
+        let height = image.height() as f32 * self.scale.y;
+        Transform::from_translate(self.point.x + width / 2.0, self.point.y + height / 2.0)
+            .pre_rotate(degrees(self.rotate))
+            .pre_translate(-width / 2.0, -height / 2.0)
+            .pre_scale(self.scale.x, self.scale.y)
+    }
+
+    /// Draws the image on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let image = decode_image(&self.base64)?;
+        let paint = PixmapPaint {
+            quality: FilterQuality::Bilinear,
+            ..Default::default()
+        };
+        pixmap.draw_pixmap(0, 0, image.as_ref(), &paint, self.transform(&image), None);
+        Ok(())
+    }
+
+    /// Returns true if a non transparent pixel of the image lands on the given point.
+    pub fn covers(&self, point: &Point) -> Result<bool, RenderError> {
+        let image = decode_image(&self.base64)?;
+        let Some(inverse) = self.transform(&image).invert() else {
+            return Ok(false);
+        };
+        let mut local = tiny_skia::Point::from_xy(point.x, point.y);
+        inverse.map_point(&mut local);
+        if local.x < 0.0 || local.y < 0.0 {
+            return Ok(false);
+        }
+        Ok(image
+            .pixel(local.x as u32, local.y as u32)
+            .is_some_and(|pixel| pixel.alpha() > 0))
+    }
 }
@@ -1,8 +1,15 @@
 use core::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
+use tiny_skia::Pixmap;
 
-use crate::{Bucket, ImageInsertion, Motion, Stroke};
+use crate::{
+    instructions::filter::{channels, premultiplied_pixel},
+    render::RenderError,
+    BlurStroke, Bucket, BucketSample, CloneStamp, Ellipse, Filter, Gradient, ImageInsertion, Line,
+    MaskApplication, Motion, Pattern, Point, Polygon, Rectangle, Selection, Smudge, Stroke,
+    SymmetricStroke, Text,
+};
 
 /// An instruction.
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -11,6 +18,137 @@ pub enum Instruction {
     ImageInsertion(ImageInsertion),
     Motion(Motion),
     Stroke(Stroke),
+    Line(Line),
+    Rectangle(Rectangle),
+    Ellipse(Ellipse),
+    Polygon(Polygon),
+    Text(Text),
+    Gradient(Gradient),
+    Filter(Filter),
+    ApplyMask(MaskApplication),
+    Smudge(Smudge),
+    BlurStroke(BlurStroke),
+    CloneStamp(CloneStamp),
+    SymmetricStroke(SymmetricStroke),
+}
+
+impl Instruction {
+    /// Returns true if the instruction captures other layers before being drawn.
+    ///
+    /// See [`crate::Drawing::capture_sources`].
+    pub fn uses_sources(&self) -> bool {
+        match self {
+            Instruction::CloneStamp(clone) => clone.source_layer.is_some(),
+            Instruction::Bucket(bucket) => bucket.sample() == BucketSample::Merged,
+            _ => false,
+        }
+    }
+
+    /// Gets the selection the instruction is clipped to, if any.
+    ///
+    /// Instructions working on strokes are clipped to the selection of their
+    /// stroke and filters to their selection. Motions already move only their
+    /// selection and mask applications change the whole layer.
+    pub fn clip(&self) -> Option<&Selection> {
+        match self {
+            Instruction::Bucket(bucket) => bucket.clip(),
+            Instruction::Stroke(stroke) => stroke.clip(),
+            Instruction::Line(line) => line.clip.as_ref(),
+            Instruction::Rectangle(rectangle) => rectangle.clip.as_ref(),
+            Instruction::Ellipse(ellipse) => ellipse.clip.as_ref(),
+            Instruction::Polygon(polygon) => polygon.clip.as_ref(),
+            Instruction::Text(text) => text.clip.as_ref(),
+            Instruction::Gradient(gradient) => gradient.clip.as_ref(),
+            Instruction::Smudge(smudge) => smudge.stroke.clip(),
+            Instruction::BlurStroke(blur) => blur.stroke.clip(),
+            Instruction::CloneStamp(clone) => clone.stroke.clip(),
+            Instruction::SymmetricStroke(symmetric) => symmetric.stroke.clip(),
+            Instruction::ImageInsertion(image) => image.clip(),
+            Instruction::Filter(filter) => filter.selection.as_ref(),
+            Instruction::Motion(_) | Instruction::ApplyMask(_) => None,
+        }
+    }
+
+    /// Applies the instruction on the given canvas.
+    ///
+    /// Only the pixels of the clip selection change, if there is one. Pattern
+    /// fills use the given patterns of the drawing.
+    pub fn render(&self, pixmap: &mut Pixmap, patterns: &[Pattern]) -> Result<(), RenderError> {
+        let Some(clip) = self.clip() else {
+            return self.paint(pixmap, patterns);
+        };
+        let mask = clip.mask(pixmap.width(), pixmap.height())?;
+        let mut painted = pixmap.clone();
+        self.paint(&mut painted, patterns)?;
+        for ((pixel, painted), coverage) in pixmap
+            .pixels_mut()
+            .iter_mut()
+            .zip(painted.pixels())
+            .zip(mask.data())
+        {
+            let (original, painted) = (channels(*pixel), channels(*painted));
+            let coverage = *coverage as f32 / 255.0;
+            let mut mixed = [0.0; 4];
+            for c in 0..4 {
+                mixed[c] = original[c] + (painted[c] - original[c]) * coverage;
+            }
+            *pixel = premultiplied_pixel(mixed);
+        }
+        Ok(())
+    }
+
+    /// Applies the instruction on the given canvas, ignoring the clip selection.
+    fn paint(&self, pixmap: &mut Pixmap, patterns: &[Pattern]) -> Result<(), RenderError> {
+        match self {
+            Instruction::Bucket(bucket) => bucket.render(pixmap, patterns),
+            Instruction::ImageInsertion(image) => image.render(pixmap),
+            Instruction::Motion(motion) => motion.render(pixmap),
+            Instruction::Stroke(stroke) => stroke.render(pixmap),
+            Instruction::Line(line) => line.render(pixmap),
+            Instruction::Rectangle(rectangle) => rectangle.render(pixmap),
+            Instruction::Ellipse(ellipse) => ellipse.render(pixmap),
+            Instruction::Polygon(polygon) => polygon.render(pixmap),
+            Instruction::Text(text) => text.render(pixmap),
+            Instruction::Gradient(gradient) => gradient.render(pixmap),
+            Instruction::Filter(filter) => filter.render(pixmap),
+            Instruction::ApplyMask(mask) => mask.render(pixmap, patterns),
+            Instruction::Smudge(smudge) => smudge.render(pixmap),
+            Instruction::BlurStroke(blur) => blur.render(pixmap),
+            Instruction::CloneStamp(clone) => clone.render(pixmap),
+            Instruction::SymmetricStroke(symmetric) => symmetric.render(pixmap),
+        }
+    }
+
+    /// Returns true if the instruction affects the given point when applied
+    /// on the given canvas.
+    pub fn covers(
+        &self,
+        point: &Point,
+        pixmap: &Pixmap,
+        patterns: &[Pattern],
+    ) -> Result<bool, RenderError> {
+        if self.clip().is_some_and(|clip| !clip.contains(point)) {
+            return Ok(false);
+        }
+        match self {
+            Instruction::Bucket(bucket) => Ok(bucket.covers(point, pixmap)),
+            Instruction::ImageInsertion(image) => image.covers(point),
+            Instruction::Motion(motion) => Ok(motion.covers(point)),
+            Instruction::Stroke(stroke) => Ok(stroke.covers(point)),
+            Instruction::Line(line) => Ok(line.covers(point)),
+            Instruction::Rectangle(rectangle) => Ok(rectangle.covers(point)),
+            Instruction::Ellipse(ellipse) => Ok(ellipse.covers(point)),
+            Instruction::Polygon(polygon) => Ok(polygon.covers(point)),
+            Instruction::Text(text) => Ok(text.covers(point)),
+            Instruction::Gradient(gradient) => Ok(gradient.covers(point, pixmap)),
+            Instruction::Filter(filter) => Ok(filter.covers(point, pixmap)),
+            Instruction::ApplyMask(mask) => mask.covers(point, pixmap, patterns),
+            Instruction::Smudge(smudge) => Ok(smudge.covers(point)),
+            Instruction::BlurStroke(blur) => Ok(blur.covers(point)),
+            Instruction::CloneStamp(clone) => Ok(clone.covers(point)),
+            Instruction::SymmetricStroke(symmetric) => Ok(symmetric.covers(point)),
+        }
+    }
 }
 
 /// An instruction box.
@@ -19,4 +157,10 @@ pub struct InstructionBox {
     pub instruction: Instruction,
     pub applied: bool,
     pub uuid: String,
+    /// The name of the user who created the instruction, if known.
+    #[serde(default)]
+    pub author: Option<String>,
+    /// When the instruction was created, in milliseconds since the Unix epoch, if known.
+    #[serde(default)]
+    pub created_at: Option<i64>,
 }
@@ -1,11 +1,25 @@
+pub mod apply_mask;
 pub mod bucket;
+pub mod filter;
+pub mod gradient;
 pub mod insert_image;
 pub mod instruction;
 pub mod motion;
+pub mod retouch;
+pub mod shape;
 pub mod stroke;
+pub mod symmetric;
+pub mod text;
 
-pub use self::bucket::Bucket;
+pub use self::apply_mask::MaskApplication;
+pub use self::bucket::{Bucket, BucketFill, BucketSample, MergedRegion, MAX_BUCKET_GROWTH};
+pub use self::filter::{Filter, FilterEffect, MAX_BLUR_RADIUS};
+pub use self::gradient::{ColorStop, FillArea, Gradient, GradientShape};
 pub use self::insert_image::ImageInsertion;
 pub use self::instruction::{Instruction, InstructionBox};
 pub use self::motion::Motion;
-pub use self::stroke::Stroke;
+pub use self::retouch::{BlurStroke, CloneSource, CloneStamp, Smudge};
+pub use self::shape::{Ellipse, Line, Polygon, Rectangle};
+pub use self::stroke::{Stroke, Tilt};
+pub use self::symmetric::SymmetricStroke;
+pub use self::text::{Text, TextAlign};
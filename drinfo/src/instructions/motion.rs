@@ -1,17 +1,22 @@
 use serde::{Deserialize, Serialize};
+use tiny_skia::{BlendMode, Paint, Pixmap, PixmapPaint, Rect, Transform};
 
-use crate::Point;
+use crate::{
+    render::{degrees, RenderError},
+    Point, Selection,
+};
 
 /// A motion instruction.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Motion {
-    /// The point where the first point of the selection ends up.
+    /// The point where the start point of the motion ends up.
     pub end: Point,
-    /// The polygon defining the selection to move, as a sequence of at least 3 points.
-    ///
-    /// Joining all the points represents the selected part that is going to be moved.
-    /// The first point is also the start point of the motion.
-    pub selection: Vec<Point>,
+    /// The selected part that is going to be moved.
+    pub selection: Selection,
+    /// The start point of the motion. Defaults to the first point of a
+    /// polygon selection, or to the top left corner of the selection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<Point>,
     /// The X and Y scale of the moved area. Defaults to (1, 1).
     #[serde(default = "default_scale")]
     pub scale: Point,
@@ -25,8 +30,92 @@ fn default_scale() -> Point {
 }
 
 impl Motion {
-    pub fn new(end: Point, selection: Vec<Point>, scale: Point, rotate: u32) -> Self {
-        Motion { end, selection, scale, rotate }
+    pub fn new(end: Point, selection: Selection, scale: Point, rotate: u32) -> Self {
+        Motion {
+            end,
+            selection,
+            start: None,
+            scale,
+            rotate,
+        }
+    }
+
+    /// Gets the start point of the motion.
+    pub fn start(&self) -> Point {
+        match (&self.start, &self.selection) {
+            (Some(start), _) => start.clone(),
+            (None, Selection::Polygon(points)) => points.first().cloned().unwrap_or_default(),
+            (None, selection) => selection
+                .bounding_box()
+                .map(|(min, _)| min)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the point the moved area is scaled and rotated around: the
+    /// centroid of a polygon selection, or the center of the selection.
+    fn pivot(&self) -> Point {
+        match &self.selection {
+            Selection::Polygon(points) => {
+                let count = points.len().max(1) as f32;
+                Point::new(
+                    points.iter().map(|p| p.x).sum::<f32>() / count,
+                    points.iter().map(|p| p.y).sum::<f32>() / count,
+                )
+            }
+            selection => selection
+                .bounding_box()
+                .map(|(min, max)| Point::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Maps the moved pixels from their original place to their destination.
+    ///
+    /// The selection is scaled and rotated around its pivot, then
+    /// translated so that the start point ends up on `end`.
+    fn transform(&self) -> Transform {
+        let Point { x: cx, y: cy } = self.pivot();
+        let start = self.start();
+        let (dx, dy) = (self.end.x - start.x, self.end.y - start.y);
+        Transform::from_translate(cx + dx, cy + dy)
+            .pre_rotate(degrees(self.rotate))
+            .pre_scale(self.scale.x, self.scale.y)
+            .pre_translate(-cx, -cy)
+    }
+
+    /// Moves the selected pixels on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let mask = self.selection.mask(pixmap.width(), pixmap.height())?;
+        let mut moved = pixmap.clone();
+        moved.apply_mask(&mask);
+
+        let paint = Paint {
+            blend_mode: BlendMode::Clear,
+            ..Default::default()
+        };
+        if let Some(rect) = Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32)
+        {
+            pixmap.fill_rect(rect, &paint, Transform::identity(), Some(&mask));
+        }
+
+        let paint = PixmapPaint {
+            quality: tiny_skia::FilterQuality::Bilinear,
+            ..Default::default()
+        };
+        pixmap.draw_pixmap(0, 0, moved.as_ref(), &paint, self.transform(), None);
+        Ok(())
+    }
+
+    /// Returns true if the point is either in the moved area or where it moved to.
+    pub fn covers(&self, point: &Point) -> bool {
+        let moved_from = self.transform().invert().map(|transform| {
+            let mut point = tiny_skia::Point::from_xy(point.x, point.y);
+            transform.map_point(&mut point);
+            Point::new(point.x, point.y)
+        });
+        self.selection.contains(point)
+            || moved_from.is_some_and(|origin| self.selection.contains(&origin))
     }
 }
 
@@ -34,7 +123,8 @@ impl Default for Motion {
     fn default() -> Self {
         Motion {
             end: Point { x: 0.0, y: 0.0 },
-            selection: vec![],
+            selection: Selection::Polygon(vec![]),
+            start: None,
             scale: Point { x: 1.0, y: 1.0 },
             rotate: 0,
         }
@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::{BlendMode, Pixmap, PixmapPaint, Transform};
+
+use crate::{
+    instructions::{
+        filter::{blur, channels, premultiplied_pixel},
+        stroke::stamp,
+    },
+    render::{decode_image, encode_image, ratio, RenderError},
+    Point, Stroke,
+};
+
+/// A smudge instruction, dragging the pixels already drawn along a stroke.
+///
+/// The brush picks up the pixels under its first stamp. At every following
+/// stamp, it lays the pixels it carries, weighted by the stamp, then keeps
+/// `strength` of what it carries and picks up the rest from the pixels under
+/// the stamp.
+///
+/// Stamps are placed like the stamps of the stroke, on whole pixels. Their
+/// weight comes from the shape, hardness and opacity of the brush; its color,
+/// texture, blend mode and size and angle jitter are ignored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Smudge {
+    pub stroke: Stroke,
+    /// How much of the carried pixels is kept between two stamps.
+    ///
+    /// [`u32::MAX`] is 1 and 0 is 0.
+    pub strength: u32,
+}
+
+/// A blur stroke instruction, softening the pixels already drawn along a stroke.
+///
+/// At every stamp, the pixels under the stamp are replaced by their gaussian
+/// blur of the given radius, computed like
+/// [`FilterEffect::Blur`](crate::FilterEffect::Blur), weighted by the stamp.
+/// Stamps are placed and weighted like the stamps of a [`Smudge`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlurStroke {
+    pub stroke: Stroke,
+    pub radius: f32,
+}
+
+/// A clone stamp instruction, copying pixels along a stroke.
+///
+/// At every stamp, the pixels at `offset` from the stamp are laid under the
+/// stamp, weighted by the stamp. Stamps are placed and weighted like the
+/// stamps of a [`Smudge`].
+///
+/// Pixels are copied from the layer of the instruction, as it is while the
+/// stroke is drawn, unless a source layer is given. The pixels of the source
+/// layer are then captured when the instruction is added to the
+/// [`Drawing`](crate::Drawing), so that later changes to the source layer do
+/// not change the instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CloneStamp {
+    pub stroke: Stroke,
+    /// Where the pixels are copied from, relative to the stamps.
+    pub offset: Point,
+    /// The name of the layer the pixels are copied from.
+    #[serde(default)]
+    pub source_layer: Option<String>,
+    /// The captured pixels of the source layer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<CloneSource>,
+}
+
+/// Pixels of a layer captured by a clone stamp.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CloneSource {
+    /// The position of the top left pixel of the image on the canvas.
+    pub x: i32,
+    pub y: i32,
+    /// The image, as a PNG data URL.
+    pub image: String,
+}
+
+impl CloneStamp {
+    pub fn new(stroke: Stroke, offset: Point, source_layer: Option<String>) -> Self {
+        CloneStamp {
+            stroke,
+            offset,
+            source_layer,
+            source: None,
+        }
+    }
+
+    /// Gets the captured pixels of the source layer, if any.
+    pub fn source(&self) -> Option<&CloneSource> {
+        self.source.as_ref()
+    }
+
+    /// Returns the top left pixel copied by every stamp.
+    fn origins(&self) -> impl Iterator<Item = ((i32, i32), (i32, i32))> + '_ {
+        let width = self.stroke.brush().width;
+        let (dx, dy) = (self.offset.x.round() as i32, self.offset.y.round() as i32);
+        self.stroke.stamps().into_iter().map(move |center| {
+            let (x, y) = origin(&center, width);
+            ((x, y), (x + dx, y + dy))
+        })
+    }
+
+    /// Forgets the captured pixels of the source layer.
+    pub(crate) fn clear_source(&mut self) {
+        self.source = None;
+    }
+
+    /// Captures the pixels of the rendered source layer that the stamps copy.
+    pub(crate) fn capture(&mut self, layer: &Pixmap) -> Result<(), RenderError> {
+        self.clear_source();
+        let size = self.stroke.brush().width.ceil().max(1.0) as i32;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+        for (_, (x, y)) in self.origins() {
+            (min_x, min_y) = (min_x.min(x), min_y.min(y));
+            (max_x, max_y) = (max_x.max(x + size), max_y.max(y + size));
+        }
+        if min_x > max_x {
+            return Ok(());
+        }
+        let image = canvas_region(layer, min_x, min_y, max_x - min_x, max_y - min_y)?;
+        self.source = Some(CloneSource {
+            x: min_x,
+            y: min_y,
+            image: encode_image(&image)?,
+        });
+        Ok(())
+    }
+
+    /// Copies pixels along the stroke on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let Some(stamp) = stamp(&self.stroke.brush()) else {
+            return Ok(());
+        };
+        let size = stamp.width() as i32;
+        // Without a source layer the stamps copy the canvas itself.
+        let source = match (&self.source_layer, &self.source) {
+            (Some(_), Some(source)) => Some((decode_image(&source.image)?, source.x, source.y)),
+            _ => None,
+        };
+        for ((x, y), (sx, sy)) in self.origins() {
+            let region = match &source {
+                Some((image, ix, iy)) => canvas_region(image, sx - ix, sy - iy, size, size)?,
+                None => canvas_region(pixmap, sx, sy, size, size)?,
+            };
+            let pixels: Vec<[f32; 4]> = region.pixels().iter().map(|p| channels(*p)).collect();
+            lay(pixmap, x, y, &stamp, |index| pixels[index]);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the clone stamp touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        self.stroke.covers(point)
+    }
+}
+
+impl Smudge {
+    pub fn new(stroke: Stroke, strength: u32) -> Self {
+        Smudge { stroke, strength }
+    }
+
+    /// Smudges the pixels of the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let Some(stamp) = stamp(&self.stroke.brush()) else {
+            return Ok(());
+        };
+        let size = stamp.width() as i32;
+        let strength = ratio(self.strength);
+        let mut carried: Option<Vec<[f32; 4]>> = None;
+        for center in self.stroke.stamps() {
+            let (x, y) = origin(&center, self.stroke.brush().width);
+            let Some(carried) = &mut carried else {
+                carried = Some(sample(pixmap, x, y, size)?);
+                continue;
+            };
+            lay(pixmap, x, y, &stamp, |index| carried[index]);
+            let picked = sample(pixmap, x, y, size)?;
+            for (carried, picked) in carried.iter_mut().zip(picked) {
+                for c in 0..4 {
+                    carried[c] = carried[c] * strength + picked[c] * (1.0 - strength);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the smudge touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        self.stroke.covers(point)
+    }
+}
+
+impl BlurStroke {
+    pub fn new(stroke: Stroke, radius: f32) -> Self {
+        BlurStroke { stroke, radius }
+    }
+
+    /// Blurs the pixels of the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let Some(stamp) = stamp(&self.stroke.brush()) else {
+            return Ok(());
+        };
+        let size = stamp.width() as i32;
+        let margin = self.radius.max(0.0).ceil() as i32;
+        let side = margin
+            .checked_mul(2)
+            .and_then(|margins| margins.checked_add(size))
+            .ok_or(RenderError::InvalidSize(u32::MAX, u32::MAX))?;
+        for center in self.stroke.stamps() {
+            let (x, y) = origin(&center, self.stroke.brush().width);
+            let region = canvas_region(pixmap, x - margin, y - margin, side, side)?;
+            let blurred = blur(&region, self.radius);
+            lay(pixmap, x, y, &stamp, |index| {
+                let (i, j) = (index as i32 % size, index as i32 / size);
+                blurred[((j + margin) * side + i + margin) as usize]
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns true if the blur touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        self.stroke.covers(point)
+    }
+}
+
+/// Returns the top left pixel of the stamp centered on the given point.
+fn origin(center: &Point, width: f32) -> (i32, i32) {
+    let half = width / 2.0;
+    (
+        (center.x - half).round() as i32,
+        (center.y - half).round() as i32,
+    )
+}
+
+/// Copies the rectangle of the canvas with the given top left pixel and size.
+///
+/// Pixels outside of the canvas are transparent.
+fn canvas_region(
+    pixmap: &Pixmap,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+) -> Result<Pixmap, RenderError> {
+    let (width, height) = (width.max(1) as u32, height.max(1) as u32);
+    let mut region = Pixmap::new(width, height).ok_or(RenderError::InvalidSize(width, height))?;
+    region.draw_pixmap(
+        -x,
+        -y,
+        pixmap.as_ref(),
+        &PixmapPaint {
+            blend_mode: BlendMode::Source,
+            ..Default::default()
+        },
+        Transform::identity(),
+        None,
+    );
+    Ok(region)
+}
+
+/// Returns the premultiplied channels of a square of the canvas.
+fn sample(pixmap: &Pixmap, x: i32, y: i32, side: i32) -> Result<Vec<[f32; 4]>, RenderError> {
+    let region = canvas_region(pixmap, x, y, side, side)?;
+    Ok(region.pixels().iter().map(|p| channels(*p)).collect())
+}
+
+/// Mixes the pixels given by `source` (by index in the stamp) into the canvas,
+/// weighted by the alpha of the stamp put at the given top left pixel.
+fn lay(pixmap: &mut Pixmap, x: i32, y: i32, stamp: &Pixmap, source: impl Fn(usize) -> [f32; 4]) {
+    let (width, height) = (pixmap.width() as i32, pixmap.height() as i32);
+    let side = stamp.width() as i32;
+    for (index, weight) in stamp.pixels().iter().enumerate() {
+        let (px, py) = (x + index as i32 % side, y + index as i32 / side);
+        let weight = weight.alpha() as f32 / 255.0;
+        if weight == 0.0 || px < 0 || py < 0 || px >= width || py >= height {
+            continue;
+        }
+        let pixel = &mut pixmap.pixels_mut()[(py * width + px) as usize];
+        let (original, source) = (channels(*pixel), source(index));
+        let mut mixed = [0.0; 4];
+        for c in 0..4 {
+            mixed[c] = original[c] + (source[c] - original[c]) * weight;
+        }
+        *pixel = premultiplied_pixel(mixed);
+    }
+}
@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::{FillRule, Paint, Pixmap, Transform};
+
+use crate::{
+    render::{degrees, polygon_contains, polygon_path, skia_color, RenderError},
+    Brush, Color, Point, Selection, Stroke,
+};
+
+/// A straight line instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Line {
+    /// Where the line starts.
+    pub start: Point,
+    /// Where the line ends.
+    pub end: Point,
+    /// The brush used to draw the line.
+    pub brush: Brush,
+    /// The selection the line is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
+}
+
+/// A rectangle instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rectangle {
+    /// A corner of the rectangle.
+    pub start: Point,
+    /// The corner opposite to `start`.
+    pub end: Point,
+    /// Rotation of the rectangle around its center. `rotate / u32::MAX * 360` = rotation in degrees.
+    #[serde(default)]
+    pub rotate: u32,
+    /// The brush used to draw the outline.
+    pub brush: Brush,
+    /// The color used to fill the rectangle, if any.
+    #[serde(default)]
+    pub fill: Option<Color>,
+    /// The selection the rectangle is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
+}
+
+/// An ellipse instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ellipse {
+    /// The center of the ellipse.
+    pub center: Point,
+    /// The X and Y radii of the ellipse.
+    pub radius: Point,
+    /// Rotation of the ellipse around its center. `rotate / u32::MAX * 360` = rotation in degrees.
+    #[serde(default)]
+    pub rotate: u32,
+    /// The brush used to draw the outline.
+    pub brush: Brush,
+    /// The color used to fill the ellipse, if any.
+    #[serde(default)]
+    pub fill: Option<Color>,
+    /// The selection the ellipse is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
+}
+
+/// A polygon instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Polygon {
+    /// The vertices of the polygon, as a sequence of at least 3 points.
+    ///
+    /// The last point is linked to the first.
+    pub points: Vec<Point>,
+    /// The brush used to draw the outline.
+    pub brush: Brush,
+    /// The color used to fill the polygon, if any.
+    #[serde(default)]
+    pub fill: Option<Color>,
+    /// The selection the polygon is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
+}
+
+impl Line {
+    pub fn new(start: Point, end: Point, brush: Brush) -> Self {
+        Line {
+            start,
+            end,
+            brush,
+            clip: None,
+        }
+    }
+
+    /// Draws the line on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        self.outline().render(pixmap)
+    }
+
+    /// Returns true if the line touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        self.outline().covers(point)
+    }
+
+    fn outline(&self) -> Stroke {
+        Stroke::new(
+            vec![self.start.clone(), self.end.clone()],
+            self.brush.clone(),
+        )
+    }
+}
+
+impl Rectangle {
+    pub fn new(start: Point, end: Point, rotate: u32, brush: Brush, fill: Option<Color>) -> Self {
+        Rectangle {
+            start,
+            end,
+            rotate,
+            brush,
+            fill,
+            clip: None,
+        }
+    }
+
+    /// Returns the corners of the rectangle.
+    pub fn corners(&self) -> Vec<Point> {
+        rectangle_corners(&self.start, &self.end, self.rotate)
+    }
+
+    /// Draws the rectangle on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        render_closed(&self.corners(), &self.brush, self.fill.as_ref(), pixmap)
+    }
+
+    /// Returns true if the outline or the fill touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        covers_closed(&self.corners(), &self.brush, self.fill.is_some(), point)
+    }
+}
+
+impl Ellipse {
+    pub fn new(
+        center: Point,
+        radius: Point,
+        rotate: u32,
+        brush: Brush,
+        fill: Option<Color>,
+    ) -> Self {
+        Ellipse {
+            center,
+            radius,
+            rotate,
+            brush,
+            fill,
+            clip: None,
+        }
+    }
+
+    /// Approximates the ellipse with a polygon whose sides are about 2 pixels long.
+    pub fn vertices(&self) -> Vec<Point> {
+        ellipse_vertices(&self.center, &self.radius, self.rotate)
+    }
+
+    /// Draws the ellipse on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        render_closed(&self.vertices(), &self.brush, self.fill.as_ref(), pixmap)
+    }
+
+    /// Returns true if the outline or the fill touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        covers_closed(&self.vertices(), &self.brush, self.fill.is_some(), point)
+    }
+}
+
+impl Polygon {
+    pub fn new(points: Vec<Point>, brush: Brush, fill: Option<Color>) -> Self {
+        Polygon {
+            points,
+            brush,
+            fill,
+            clip: None,
+        }
+    }
+
+    /// Draws the polygon on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        render_closed(&self.points, &self.brush, self.fill.as_ref(), pixmap)
+    }
+
+    /// Returns true if the outline or the fill touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        covers_closed(&self.points, &self.brush, self.fill.is_some(), point)
+    }
+}
+
+/// Returns the corners of the rectangle going from `start` to the opposite
+/// corner `end`, rotated around its center.
+pub(crate) fn rectangle_corners(start: &Point, end: &Point, rotate: u32) -> Vec<Point> {
+    let center = Point::new((start.x + end.x) / 2.0, (start.y + end.y) / 2.0);
+    let transform = Transform::from_rotate_at(degrees(rotate), center.x, center.y);
+    [
+        (start.x, start.y),
+        (end.x, start.y),
+        (end.x, end.y),
+        (start.x, end.y),
+    ]
+    .into_iter()
+    .map(|(x, y)| map_point(&transform, x, y))
+    .collect()
+}
+
+/// Approximates the ellipse with a polygon whose sides are about 2 pixels long.
+pub(crate) fn ellipse_vertices(center: &Point, radius: &Point, rotate: u32) -> Vec<Point> {
+    let (rx, ry) = (radius.x.abs(), radius.y.abs());
+    // Ramanujan's approximation of the perimeter.
+    let perimeter =
+        std::f32::consts::PI * (3.0 * (rx + ry) - ((3.0 * rx + ry) * (rx + 3.0 * ry)).sqrt());
+    let count = ((perimeter / 2.0).ceil() as usize).max(16);
+    let transform = Transform::from_translate(center.x, center.y).pre_rotate(degrees(rotate));
+    (0..count)
+        .map(|i| {
+            let angle = i as f32 / count as f32 * std::f32::consts::TAU;
+            map_point(&transform, rx * angle.cos(), ry * angle.sin())
+        })
+        .collect()
+}
+
+fn map_point(transform: &Transform, x: f32, y: f32) -> Point {
+    let mut point = tiny_skia::Point::from_xy(x, y);
+    transform.map_point(&mut point);
+    Point::new(point.x, point.y)
+}
+
+/// Returns the outline of a closed shape as a stroke going back to its first point.
+fn closed_outline(vertices: &[Point], brush: &Brush) -> Stroke {
+    let mut points = vertices.to_vec();
+    if let Some(first) = vertices.first() {
+        points.push(first.clone());
+    }
+    Stroke::new(points, brush.clone())
+}
+
+/// Fills a closed shape, then draws its outline on top of the fill.
+///
+/// The fill uses the opacity and the blend mode of the brush, and erases if
+/// the brush erases.
+fn render_closed(
+    vertices: &[Point],
+    brush: &Brush,
+    fill: Option<&Color>,
+    pixmap: &mut Pixmap,
+) -> Result<(), RenderError> {
+    if let (Some(fill), Some(path)) = (fill, polygon_path(vertices)) {
+        let mut paint = Paint {
+            anti_alias: true,
+            blend_mode: brush.skia_blend_mode(),
+            ..Default::default()
+        };
+        paint.set_color(skia_color(fill, brush.opacity));
+        pixmap.fill_path(
+            &path,
+            &paint,
+            FillRule::EvenOdd,
+            Transform::identity(),
+            None,
+        );
+    }
+    closed_outline(vertices, brush).render(pixmap)
+}
+
+fn covers_closed(vertices: &[Point], brush: &Brush, filled: bool, point: &Point) -> bool {
+    (filled && polygon_contains(vertices, point)) || closed_outline(vertices, brush).covers(point)
+}
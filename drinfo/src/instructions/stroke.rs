@@ -1,16 +1,85 @@
-use crate::{Brush, Point};
+use crate::{
+    render::{canvas, polygon_path, ratio, skia_color, Random, RenderError},
+    Brush, BrushShape, Point, Selection,
+};
 use serde::{Deserialize, Serialize};
+use tiny_skia::{
+    BlendMode, FillRule, FilterQuality, GradientStop, IntSize, Mask, Paint, PathBuilder, Pixmap,
+    PixmapPaint, RadialGradient, Rect, SpreadMode, Transform,
+};
 
 /// A stroke instruction.
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct Stroke {
     points: Vec<Point>,
     brush: Brush,
+    /// The stylus pressure at every point, [`u16::MAX`] being full pressure.
+    ///
+    /// Empty when the stroke was drawn without pressure.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pressures: Vec<u16>,
+    /// The stylus tilt at every point, empty when the stroke was drawn without tilt.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tilts: Vec<Tilt>,
+    /// The seed of the random values used by the jitter of the brush.
+    ///
+    /// A `u32` so that JavaScript clients can hold it exactly.
+    #[serde(default)]
+    seed: u32,
+    /// The selection the stroke is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clip: Option<Selection>,
+}
+
+/// The largest number of stamps placed between two points of a stroke.
+const MAX_SEGMENT_STAMPS: usize = 1 << 18;
+
+/// The tilt of a stylus, in degrees from the perpendicular to the canvas
+/// (from -90 to 90) along each axis.
+///
+/// Tilt is recorded for clients, it does not change how strokes are drawn.
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tilt {
+    pub x: i8,
+    pub y: i8,
+}
+
+/// A brush stamp along a stroke.
+struct Stamp {
+    center: Point,
+    /// The size of the stamp relative to the brush width.
+    scale: f32,
+    /// The opacity of the stamp, between 0 and 1.
+    opacity: f32,
+    /// The rotation of the stamp, in degrees.
+    angle: f32,
 }
 
 impl Stroke {
     pub fn new(points: Vec<Point>, brush: Brush) -> Self {
-        Stroke { points, brush }
+        Stroke {
+            points,
+            brush,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a stroke drawn with a stylus.
+    ///
+    /// `pressures` and `tilts` are either empty or have a value for every point.
+    pub fn with_stylus(
+        points: Vec<Point>,
+        brush: Brush,
+        pressures: Vec<u16>,
+        tilts: Vec<Tilt>,
+    ) -> Self {
+        Stroke {
+            points,
+            brush,
+            pressures,
+            tilts,
+            ..Default::default()
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -31,8 +100,479 @@ impl Stroke {
         self.brush.clone()
     }
 
+    /// Gets the stroke's points.
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Gets the seed of the random values used by the jitter of the brush.
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Changes the seed of the random values used by the jitter of the brush.
+    ///
+    /// Clients should pick a random seed for every new stroke so that strokes
+    /// drawn with jitter do not all look the same.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+    }
+
+    /// Gets the selection the stroke is clipped to, if any.
+    pub fn clip(&self) -> Option<&Selection> {
+        self.clip.as_ref()
+    }
+
+    /// Clips the stroke to the given selection, so that it only paints the
+    /// selected pixels.
+    pub fn set_clip(&mut self, clip: Option<Selection>) {
+        self.clip = clip;
+    }
+
+    /// Gets the stylus pressure at every point, empty if the stroke has no pressure.
+    pub fn pressures(&self) -> &[u16] {
+        &self.pressures
+    }
+
+    /// Gets the stylus tilt at every point, empty if the stroke has no tilt.
+    pub fn tilts(&self) -> &[Tilt] {
+        &self.tilts
+    }
+
+    /// Returns the pressure at the given point between 0 and 1, full
+    /// pressure if the stroke has no pressure.
+    pub fn pressure_at(&self, index: usize) -> f32 {
+        self.pressures
+            .get(index)
+            .map(|pressure| *pressure as f32 / u16::MAX as f32)
+            .unwrap_or(1.0)
+    }
+
+    /// Returns the same stroke with every point moved by the given function.
+    pub(crate) fn map_points(&self, f: impl Fn(&Point) -> Point) -> Stroke {
+        Stroke {
+            points: self.points.iter().map(f).collect(),
+            ..self.clone()
+        }
+    }
+
     /// Adds a new point to the stroke.
+    ///
+    /// If the stroke has pressure or tilt, the point gets full pressure and no tilt.
     pub fn add_point(&mut self, point: Point) {
+        self.add_stylus_point(point, None, None);
+    }
+
+    /// Adds a new point drawn with a stylus to the stroke.
+    ///
+    /// Previous points get full pressure and no tilt when the stroke did not
+    /// have them yet.
+    pub fn add_stylus_point(&mut self, point: Point, pressure: Option<u16>, tilt: Option<Tilt>) {
+        if pressure.is_some() || !self.pressures.is_empty() {
+            self.pressures.resize(self.points.len(), u16::MAX);
+            self.pressures.push(pressure.unwrap_or(u16::MAX));
+        }
+        if tilt.is_some() || !self.tilts.is_empty() {
+            self.tilts.resize(self.points.len(), Tilt::default());
+            self.tilts.push(tilt.unwrap_or_default());
+        }
         self.points.push(point);
     }
+
+    /// Returns the centers of every brush stamp along the stroke.
+    ///
+    /// The first point is always stamped, then the brush is stamped every
+    /// `repeat * width` pixels along the path (at least every pixel), before
+    /// the stamps are scattered by the jitter of the brush.
+    pub fn stamps(&self) -> Vec<Point> {
+        self.placed_stamps()
+            .into_iter()
+            .map(|stamp| stamp.center)
+            .collect()
+    }
+
+    /// Returns every brush stamp, with the pressure interpolated between
+    /// points and the jitter applied.
+    ///
+    /// Every stamp has its own random values, so adding points to a stroke
+    /// does not change the stamps already drawn.
+    ///
+    /// At most [`MAX_SEGMENT_STAMPS`] stamps are placed between two points.
+    fn placed_stamps(&self) -> Vec<Stamp> {
+        let Some(first) = self.points.first() else {
+            return vec![];
+        };
+        let spacing = (ratio(self.brush.repeat) * self.brush.width).max(1.0);
+        let mut stamps = vec![(first.clone(), self.pressure_at(0))];
+        // Distance from the start of the segment to its first stamp.
+        let mut offset = spacing;
+        for (index, segment) in self.points.windows(2).enumerate() {
+            let (a, b) = (&segment[0], &segment[1]);
+            let (pa, pb) = (self.pressure_at(index), self.pressure_at(index + 1));
+            let length = ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+            if length == 0.0 || !length.is_finite() || offset > length {
+                offset -= length.min(offset);
+                continue;
+            }
+            // Stamps are counted with an integer so that long segments end.
+            let count = (((length - offset) / spacing) as usize + 1).min(MAX_SEGMENT_STAMPS);
+            for step in 0..count {
+                let t = (offset + step as f32 * spacing) / length;
+                stamps.push((
+                    Point::new(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t),
+                    pa + (pb - pa) * t,
+                ));
+            }
+            offset = (offset + count as f32 * spacing - length).max(0.0);
+        }
+
+        let jitter = &self.brush.jitter;
+        stamps
+            .into_iter()
+            .enumerate()
+            .map(|(index, (center, pressure))| {
+                let (scale, opacity) = self.brush.dynamics.factors(pressure);
+                let mut random = Random::new(self.seed as u64, index as u64);
+                let direction = random.next_f32() * std::f32::consts::TAU;
+                let distance = random.next_f32() * ratio(jitter.scatter) * self.brush.width;
+                Stamp {
+                    center: Point::new(
+                        center.x + direction.cos() * distance,
+                        center.y + direction.sin() * distance,
+                    ),
+                    scale: scale * (1.0 - random.next_f32() * ratio(jitter.size)),
+                    opacity: opacity * (1.0 - random.next_f32() * ratio(jitter.opacity)),
+                    angle: random.next_f32() * ratio(jitter.angle) * 360.0,
+                }
+            })
+            .collect()
+    }
+
+    /// Draws the stroke on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let Some(stamp) = stamp(&self.brush) else {
+            return Ok(());
+        };
+        let blend_mode = self.brush.skia_blend_mode();
+        // Strokes with a texture or a blend mode are drawn apart, then painted
+        // through the texture and blended with the canvas as a whole.
+        let mut separate = match blend_mode {
+            BlendMode::SourceOver | BlendMode::DestinationOut if self.brush.texture.is_none() => {
+                None
+            }
+            _ => Some(canvas(pixmap.width(), pixmap.height())?),
+        };
+        let mut paint = PixmapPaint {
+            blend_mode: if separate.is_some() {
+                BlendMode::SourceOver
+            } else {
+                blend_mode
+            },
+            ..Default::default()
+        };
+        let half = self.brush.width / 2.0;
+        for Stamp {
+            center,
+            scale,
+            opacity,
+            angle,
+        } in self.placed_stamps()
+        {
+            paint.opacity = opacity;
+            paint.quality = if scale == 1.0 && angle == 0.0 {
+                FilterQuality::Nearest
+            } else {
+                FilterQuality::Bilinear
+            };
+            separate.as_mut().unwrap_or(&mut *pixmap).draw_pixmap(
+                0,
+                0,
+                stamp.as_ref(),
+                &paint,
+                Transform::from_translate(center.x, center.y)
+                    .pre_rotate(angle)
+                    .pre_scale(scale, scale)
+                    .pre_translate(-half, -half),
+                None,
+            );
+        }
+
+        if let Some(mut separate) = separate {
+            if let Some(texture) = &self.brush.texture {
+                let (width, height) = (pixmap.width(), pixmap.height());
+                let coverage = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| (texture.coverage(x, y) * 255.0).round() as u8)
+                    .collect();
+                if let Some(mask) =
+                    IntSize::from_wh(width, height).and_then(|size| Mask::from_vec(coverage, size))
+                {
+                    separate.apply_mask(&mask);
+                }
+            }
+            pixmap.draw_pixmap(
+                0,
+                0,
+                separate.as_ref(),
+                &PixmapPaint {
+                    blend_mode,
+                    ..Default::default()
+                },
+                Transform::identity(),
+                None,
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns true if any stamp of the stroke touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        let half = self.brush.width / 2.0;
+        self.placed_stamps().iter().any(|stamp| {
+            // Brings the point back to the brush square, before rotation and scaling.
+            let (sin, cos) = (-stamp.angle.to_radians()).sin_cos();
+            let (dx, dy) = (point.x - stamp.center.x, point.y - stamp.center.y);
+            let scale = stamp.scale.max(f32::EPSILON);
+            let (dx, dy) = ((dx * cos - dy * sin) / scale, (dx * sin + dy * cos) / scale);
+            match &self.brush.brush_shape {
+                BrushShape::Circle => dx * dx + dy * dy < half * half,
+                BrushShape::Square => dx.abs() <= half && dy.abs() <= half,
+                BrushShape::Custom(custom) => {
+                    let width = self.brush.width.max(f32::EPSILON);
+                    custom.coverage((dx + half) / width, (dy + half) / width) > 0.0
+                }
+            }
+        })
+    }
+}
+
+/// Generates the image stamped along a stroke for the given brush.
+pub(crate) fn stamp(brush: &Brush) -> Option<Pixmap> {
+    let size = brush.width.ceil().max(1.0) as u32;
+    let mut pixmap = Pixmap::new(size, size)?;
+    let color = skia_color(&brush.color, brush.opacity);
+    let mut paint = Paint::default();
+    paint.set_color(color);
+    paint.anti_alias = true;
+    match &brush.brush_shape {
+        BrushShape::Circle => {
+            let radius = brush.width / 2.0;
+            let center = tiny_skia::Point::from_xy(radius, radius);
+            let mut transparent = color;
+            transparent.set_alpha(0.0);
+            if let Some(shader) = RadialGradient::new(
+                center,
+                center,
+                radius,
+                vec![
+                    GradientStop::new(ratio(brush.hardness), color),
+                    GradientStop::new(1.0, transparent),
+                ],
+                SpreadMode::Pad,
+                Transform::identity(),
+            ) {
+                paint.shader = shader;
+            }
+            let path = PathBuilder::from_circle(radius, radius, radius)?;
+            pixmap.fill_path(
+                &path,
+                &paint,
+                FillRule::Winding,
+                Transform::identity(),
+                None,
+            );
+        }
+        BrushShape::Square => {
+            let rect = Rect::from_xywh(0.0, 0.0, brush.width, brush.width)?;
+            pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+        }
+        BrushShape::Custom(custom) => match custom.bitmap() {
+            Some(bitmap) => {
+                let mut image = Pixmap::new(bitmap.width(), bitmap.height())?;
+                for (pixel, gray) in image.pixels_mut().iter_mut().zip(bitmap.data()) {
+                    let mut color = color;
+                    color.apply_opacity(1.0 - *gray as f32 / 255.0);
+                    *pixel = color.premultiply().to_color_u8();
+                }
+                let scale = brush.width / bitmap.width().max(bitmap.height()) as f32;
+                let paint = PixmapPaint {
+                    quality: FilterQuality::Bilinear,
+                    ..Default::default()
+                };
+                pixmap.draw_pixmap(
+                    0,
+                    0,
+                    image.as_ref(),
+                    &paint,
+                    Transform::from_translate(
+                        (brush.width - bitmap.width() as f32 * scale) / 2.0,
+                        (brush.width - bitmap.height() as f32 * scale) / 2.0,
+                    )
+                    .pre_scale(scale, scale),
+                    None,
+                );
+            }
+            None => {
+                for polygon in custom.polygons() {
+                    if let Some(path) = polygon_path(polygon) {
+                        pixmap.fill_path(
+                            &path,
+                            &paint,
+                            FillRule::EvenOdd,
+                            Transform::from_scale(brush.width, brush.width),
+                            None,
+                        );
+                    }
+                }
+            }
+        },
+    }
+    Some(pixmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BrushJitter, BrushTexture};
+
+    fn stroke(points: &[(f32, f32)]) -> Stroke {
+        let brush = Brush {
+            width: 2.0,
+            repeat: u32::MAX,
+            ..Default::default()
+        };
+        Stroke::new(
+            points.iter().map(|&(x, y)| Point::new(x, y)).collect(),
+            brush,
+        )
+    }
+
+    fn xs(stroke: &Stroke) -> Vec<f32> {
+        stroke.stamps().iter().map(|stamp| stamp.x).collect()
+    }
+
+    #[test]
+    fn stamps_are_spaced_along_the_path() {
+        assert_eq!(
+            xs(&stroke(&[(0.0, 0.0), (10.0, 0.0)])),
+            [0.0, 2.0, 4.0, 6.0, 8.0, 10.0]
+        );
+        // The spacing carries over from one segment to the next.
+        assert_eq!(
+            xs(&stroke(&[(0.0, 0.0), (3.0, 0.0), (3.0, 0.0), (10.0, 0.0)])),
+            [0.0, 2.0, 4.0, 6.0, 8.0, 10.0]
+        );
+        assert_eq!(xs(&stroke(&[(0.0, 0.0), (1.0, 0.0)])), [0.0]);
+        assert!(stroke(&[]).stamps().is_empty());
+    }
+
+    #[test]
+    fn stamps_are_capped_per_segment() {
+        let long = stroke(&[(0.0, 0.0), (1e9, 0.0), (1e9, 10.0)]);
+        // The capped segment carries nothing over, so the next one starts afresh.
+        assert_eq!(long.stamps().len(), 1 + MAX_SEGMENT_STAMPS + 6);
+
+        let infinite = stroke(&[(0.0, 0.0), (f32::INFINITY, 0.0), (f32::NAN, 3.0)]);
+        assert_eq!(infinite.stamps().len(), 1);
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn strokes_saved_before_stylus_support_load_without_pressure() {
+        let json = r#"{"points":[{"x":1,"y":1},{"x":5,"y":1}],"brush":{"color":{"r":0,"g":0,"b":0},"width":4,"hardness":0,"brush_shape":"Circle","opacity":100000,"erase":false,"repeat":2}}"#;
+        let stroke: Stroke = serde_json::from_str(json).unwrap();
+        assert!(stroke.pressures().is_empty());
+        assert!(stroke.tilts().is_empty());
+        assert_eq!(stroke.pressure_at(1), 1.0);
+        assert!(stroke
+            .placed_stamps()
+            .iter()
+            .all(|stamp| stamp.scale == 1.0 && stamp.opacity == 1.0));
+
+        let json = serde_json::to_value(&stroke).unwrap();
+        assert!(json.get("pressures").is_none() && json.get("tilts").is_none());
+    }
+
+    #[test]
+    fn stylus_points_backfill_earlier_points() {
+        let mut stroke = Stroke::default();
+        stroke.add_point(Point::new(0.0, 0.0));
+        assert!(stroke.pressures().is_empty());
+
+        stroke.add_stylus_point(Point::new(1.0, 0.0), Some(100), None);
+        assert_eq!(stroke.pressures(), [u16::MAX, 100]);
+        assert!(stroke.tilts().is_empty());
+
+        stroke.add_point(Point::new(2.0, 0.0));
+        let tilt = Tilt { x: 10, y: -5 };
+        stroke.add_stylus_point(Point::new(3.0, 0.0), None, Some(tilt));
+        assert_eq!(stroke.pressures(), [u16::MAX, 100, u16::MAX, u16::MAX]);
+        assert_eq!(
+            stroke.tilts(),
+            [Tilt::default(), Tilt::default(), Tilt::default(), tilt]
+        );
+    }
+
+    #[test]
+    fn pressure_scales_and_fades_stamps() {
+        let mut brush = Brush {
+            width: 2.0,
+            repeat: u32::MAX,
+            ..Default::default()
+        };
+        brush.dynamics.pressure_width = u32::MAX;
+        brush.dynamics.pressure_opacity = u32::MAX / 2;
+        assert_eq!(brush.dynamics.factors(1.0), (1.0, 1.0));
+        assert_eq!(brush.dynamics.factors(-1.0), brush.dynamics.factors(0.0));
+        let (scale, opacity) = brush.dynamics.factors(0.25);
+        assert!(close(scale, 0.25) && close(opacity, 0.625));
+
+        let points = vec![Point::new(0.0, 0.0), Point::new(8.0, 0.0)];
+        let stroke = Stroke::with_stylus(points, brush, vec![u16::MAX, 0], vec![]);
+        let stamps = stroke.placed_stamps();
+        assert_eq!(stamps.len(), 5);
+        let scales: Vec<f32> = stamps.iter().map(|stamp| stamp.scale).collect();
+        let opacities: Vec<f32> = stamps.iter().map(|stamp| stamp.opacity).collect();
+        for (actual, expected) in scales.iter().zip([1.0, 0.75, 0.5, 0.25, 0.0]) {
+            assert!(close(*actual, expected), "{scales:?}");
+        }
+        for (actual, expected) in opacities.iter().zip([1.0, 0.875, 0.75, 0.625, 0.5]) {
+            assert!(close(*actual, expected), "{opacities:?}");
+        }
+    }
+
+    #[test]
+    fn jittered_strokes_only_depend_on_their_seed() {
+        let quarter = u32::MAX / 4;
+        let mut brush = Brush {
+            width: 6.0,
+            opacity: u32::MAX,
+            repeat: quarter,
+            ..Default::default()
+        };
+        brush.jitter = BrushJitter {
+            scatter: quarter,
+            size: quarter,
+            opacity: quarter,
+            angle: quarter,
+        };
+        let texture = BrushTexture::from_bitmap(2, 2, vec![0, 128, 255, 64], u32::MAX).unwrap();
+        brush.set_texture(Some(texture));
+        let render = |seed: u32| {
+            let mut stroke = Stroke::new(
+                vec![Point::new(4.0, 16.0), Point::new(28.0, 16.0)],
+                brush.clone(),
+            );
+            stroke.set_seed(seed);
+            let mut pixmap = canvas(32, 32).unwrap();
+            stroke.render(&mut pixmap).unwrap();
+            pixmap.data().to_vec()
+        };
+        assert_eq!(render(7), render(7));
+        assert_ne!(render(7), render(8));
+        assert_ne!(render(u32::MAX), render(u32::MAX - 1));
+    }
 }
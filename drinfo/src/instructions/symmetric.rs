@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::Pixmap;
+
+use crate::{render::RenderError, Point, Stroke, Symmetry};
+
+/// A stroke drawn in symmetry mode, drawn once for every copy of the symmetry.
+///
+/// The symmetry is stored with the stroke, so that changing the symmetry of
+/// the [`Drawing`](crate::Drawing) does not change the strokes already drawn.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SymmetricStroke {
+    pub stroke: Stroke,
+    pub symmetry: Symmetry,
+}
+
+impl SymmetricStroke {
+    pub fn new(stroke: Stroke, symmetry: Symmetry) -> Self {
+        SymmetricStroke { stroke, symmetry }
+    }
+
+    /// Returns the stroke as drawn in every copy, the original first.
+    pub fn strokes(&self) -> Vec<Stroke> {
+        (0..self.symmetry.copies())
+            .map(|copy| {
+                self.stroke
+                    .map_points(|point| self.symmetry.image(point, copy))
+            })
+            .collect()
+    }
+
+    /// Draws every copy of the stroke on the given canvas, one after the other.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        for stroke in self.strokes() {
+            stroke.render(pixmap)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if any copy of the stroke touches the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        self.strokes().iter().any(|stroke| stroke.covers(point))
+    }
+}
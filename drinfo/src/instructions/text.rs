@@ -0,0 +1,171 @@
+use ab_glyph::{Font, OutlineCurve};
+use serde::{Deserialize, Serialize};
+use tiny_skia::{FillRule, Mask, Paint, Path, PathBuilder, Pixmap, Transform};
+
+use crate::{
+    render::{degrees, font, skia_color, RenderError},
+    Color, Point, Selection,
+};
+
+/// A text instruction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Text {
+    /// The text to write, lines are separated by `\n`.
+    pub text: String,
+    /// The anchor of the text, on the baseline of the first line.
+    ///
+    /// Depending on `align`, the anchor is at the left, the center or the right of each line.
+    pub point: Point,
+    /// The font family used to write the text.
+    ///
+    /// Server side rendering always uses the bundled DejaVu Sans font.
+    pub font_family: String,
+    /// The size of the font in pixels.
+    pub font_size: f32,
+    /// The color of the text.
+    pub color: Color,
+    /// How lines are aligned relative to the anchor. Defaults to left.
+    #[serde(default)]
+    pub align: TextAlign,
+    /// Rotation of the text around its anchor. `rotate / u32::MAX * 360` = rotation in degrees. Defaults to 0.
+    #[serde(default)]
+    pub rotate: u32,
+    /// The selection the text is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
+}
+
+/// The horizontal alignment of a text.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl Text {
+    pub fn new(
+        text: &str,
+        point: Point,
+        font_family: &str,
+        font_size: f32,
+        color: Color,
+        align: TextAlign,
+        rotate: u32,
+    ) -> Self {
+        Text {
+            text: String::from(text),
+            point,
+            font_family: String::from(font_family),
+            font_size,
+            color,
+            align,
+            rotate,
+            clip: None,
+        }
+    }
+
+    /// Lays the glyphs of the text out, relative to the anchor and without rotation.
+    fn path(&self) -> Option<Path> {
+        let font = font();
+        let scale = self.font_size / font.units_per_em()?;
+        let line_height =
+            (font.ascent_unscaled() - font.descent_unscaled() + font.line_gap_unscaled()) * scale;
+        let mut builder = PathBuilder::new();
+        for (line_index, line) in self.text.lines().enumerate() {
+            let glyphs: Vec<_> = line.chars().map(|c| font.glyph_id(c)).collect();
+            let width: f32 = glyphs
+                .iter()
+                .enumerate()
+                .map(|(i, glyph)| {
+                    let kern = glyphs
+                        .get(i + 1)
+                        .map(|next| font.kern_unscaled(*glyph, *next))
+                        .unwrap_or_default();
+                    (font.h_advance_unscaled(*glyph) + kern) * scale
+                })
+                .sum();
+            let mut x = match self.align {
+                TextAlign::Left => 0.0,
+                TextAlign::Center => -width / 2.0,
+                TextAlign::Right => -width,
+            };
+            let y = line_index as f32 * line_height;
+            for (i, glyph) in glyphs.iter().enumerate() {
+                if let Some(outline) = font.outline(*glyph) {
+                    let mut last = None;
+                    for curve in &outline.curves {
+                        let map = |p: &ab_glyph::Point| (x + p.x * scale, y - p.y * scale);
+                        let start = match curve {
+                            OutlineCurve::Line(a, _)
+                            | OutlineCurve::Quad(a, _, _)
+                            | OutlineCurve::Cubic(a, _, _, _) => *a,
+                        };
+                        if last != Some(start) {
+                            if last.is_some() {
+                                builder.close();
+                            }
+                            let (sx, sy) = map(&start);
+                            builder.move_to(sx, sy);
+                        }
+                        last = Some(match curve {
+                            OutlineCurve::Line(_, b) => {
+                                let (bx, by) = map(b);
+                                builder.line_to(bx, by);
+                                *b
+                            }
+                            OutlineCurve::Quad(_, c, b) => {
+                                let ((cx, cy), (bx, by)) = (map(c), map(b));
+                                builder.quad_to(cx, cy, bx, by);
+                                *b
+                            }
+                            OutlineCurve::Cubic(_, c1, c2, b) => {
+                                let ((c1x, c1y), (c2x, c2y), (bx, by)) = (map(c1), map(c2), map(b));
+                                builder.cubic_to(c1x, c1y, c2x, c2y, bx, by);
+                                *b
+                            }
+                        });
+                    }
+                    if last.is_some() {
+                        builder.close();
+                    }
+                }
+                let kern = glyphs
+                    .get(i + 1)
+                    .map(|next| font.kern_unscaled(*glyph, *next))
+                    .unwrap_or_default();
+                x += (font.h_advance_unscaled(*glyph) + kern) * scale;
+            }
+        }
+        builder.finish()
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::from_translate(self.point.x, self.point.y).pre_rotate(degrees(self.rotate))
+    }
+
+    /// Writes the text on the given canvas.
+    pub fn render(&self, pixmap: &mut Pixmap) -> Result<(), RenderError> {
+        let Some(path) = self.path() else {
+            return Ok(());
+        };
+        let mut paint = Paint {
+            anti_alias: true,
+            ..Default::default()
+        };
+        paint.set_color(skia_color(&self.color, u32::MAX));
+        pixmap.fill_path(&path, &paint, FillRule::Winding, self.transform(), None);
+        Ok(())
+    }
+
+    /// Returns true if a glyph of the text covers the given point.
+    pub fn covers(&self, point: &Point) -> bool {
+        let (Some(path), Some(mut mask)) = (self.path(), Mask::new(1, 1)) else {
+            return false;
+        };
+        let transform = self.transform().post_translate(-point.x, -point.y);
+        mask.fill_path(&path, FillRule::Winding, true, transform);
+        mask.data()[0] > 0
+    }
+}
@@ -2,8 +2,15 @@ use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tiny_skia::Pixmap;
 
-use crate::{Instruction, InstructionBox};
+use crate::{
+    brush::BrushError,
+    render::{canvas, decode_image, RenderError},
+    BucketFill, FillArea, Filter, FilterEffect, Gradient, Instruction, InstructionBox, LayerMask,
+    MaskApplication, Pattern, Point, SelectionError, SymmetryError, MAX_BLUR_RADIUS,
+    MAX_BUCKET_GROWTH, MAX_COORDINATE,
+};
 
 /// A layer.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,6 +19,11 @@ pub struct Layer {
     history: Vec<InstructionBox>,
     history_index: u64,
     visible: bool,
+    #[serde(default)]
+    mask: Option<Box<LayerMask>>,
+    /// Restricts the layer to the pixels of the nearest non-clipping layer below it.
+    #[serde(default)]
+    clip_to_below: bool,
 }
 
 #[derive(Error, Debug)]
@@ -24,8 +36,34 @@ pub enum LayerError {
     InvalidHistoryMove(u64, u64),
     #[error("history index {0} does not exist")]
     InvalidHistoryIndex(u64),
+    #[error("only texts can be edited")]
+    NotEditable,
     #[error("invalid stroke, stroke must have at least 1 point")]
     MinStrokePoints,
+    #[error("invalid stroke, pressure and tilt must be given for every point or none")]
+    StylusPointsMismatch,
+    #[error("invalid point, coordinates must be finite and at most {MAX_COORDINATE} away from 0")]
+    PointOutOfRange,
+    #[error("invalid polygon, polygon must have at least 3 points")]
+    MinPolygonPoints,
+    #[error("invalid gradient, gradient must have at least 1 color stop")]
+    MinGradientStops,
+    #[error("invalid brush: {0}")]
+    InvalidBrush(#[from] BrushError),
+    #[error("invalid bucket fill, cannot grow by {0} pixels, the maximum is {MAX_BUCKET_GROWTH}")]
+    InvalidBucketGrowth(u32),
+    #[error("invalid pattern fill, scale {0} must be finite and above 0")]
+    InvalidPatternScale(f32),
+    #[error("invalid blur, radius {0} must be finite and between 0 and {MAX_BLUR_RADIUS}")]
+    InvalidBlurRadius(f32),
+    #[error("invalid symmetry: {0}")]
+    InvalidSymmetry(#[from] SymmetryError),
+    #[error("invalid selection: {0}")]
+    InvalidSelection(#[from] SelectionError),
+    #[error("layer already has a mask")]
+    MaskAlreadyExists,
+    #[error("layer does not have a mask")]
+    NoMask,
     #[error("layer does not have a snapshot at index {0}, must have one in order to truncate")]
     NoSnapshot(u64),
 }
@@ -37,6 +75,8 @@ impl Default for Layer {
             history: Default::default(),
             history_index: 0,
             visible: true,
+            mask: None,
+            clip_to_below: false,
         }
     }
 }
@@ -91,6 +131,23 @@ impl Layer {
         }
     }
 
+    /// Hides every applied instruction created by the given author.
+    ///
+    /// Returns the history index of every hidden instruction.
+    pub fn revert_author(&mut self, author: &str) -> Result<Vec<u64>, LayerError> {
+        let indices: Vec<u64> = self
+            .history
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.applied && i.author.as_deref() == Some(author))
+            .map(|(index, _)| index as u64 + 1)
+            .collect();
+        for index in &indices {
+            self.set_instruction_visibility(*index, false)?;
+        }
+        Ok(indices)
+    }
+
     /// Clears the layer.
     ///
     /// The layer is cleared by removing the history. You cannot undo this.
@@ -102,17 +159,40 @@ impl Layer {
 
     /// Adds the given instruction to the layer.
     pub fn instruct(&mut self, instruction: InstructionBox) -> Result<(), LayerError> {
-        if let Instruction::Stroke(s) = &instruction.instruction {
-            if s.is_empty() {
-                return Err(LayerError::MinStrokePoints);
-            }
-        }
+        validate(&instruction.instruction)?;
         self.history.truncate(self.history_index as usize);
         self.history.push(instruction);
         self.history_index += 1;
         Ok(())
     }
 
+    /// Replaces a text of the history with another text, keeping its uuid,
+    /// author and visibility.
+    ///
+    /// This is how a text is edited after the fact. Other instructions cannot
+    /// be edited, so that they stay attributed to the user who made them.
+    pub fn replace_instruction(
+        &mut self,
+        index: u64,
+        instruction: Instruction,
+    ) -> Result<(), LayerError> {
+        validate(&instruction)?;
+        if index > 0 && index <= self.history.len() as u64 {
+            let replaced = &mut self.history[index as usize - 1].instruction;
+            if !matches!(
+                (&*replaced, &instruction),
+                (Instruction::Text(_), Instruction::Text(_))
+            ) {
+                return Err(LayerError::NotEditable);
+            }
+            *replaced = instruction;
+            self.invalidate_snapshots(index);
+            Ok(())
+        } else {
+            Err(LayerError::InvalidHistoryIndex(index))
+        }
+    }
+
     /// Remove an instruction from history.
     pub fn remove_instruction(&mut self, index: u64) -> Result<(), LayerError> {
         if index > 0 && index <= self.history.len() as u64 {
@@ -132,6 +212,11 @@ impl Layer {
         self.visible = visible;
     }
 
+    /// Sets whether the layer is clipped to the layer below it.
+    pub fn set_clip_to_below(&mut self, clip_to_below: bool) {
+        self.clip_to_below = clip_to_below;
+    }
+
     /// Saves the given image as a snapshot of the current history index.
     pub fn snapshot(&mut self, index: u64, data: String) {
         self.snapshots.insert(index, data);
@@ -158,6 +243,12 @@ impl Layer {
         self.visible
     }
 
+    /// Returns true if the layer is only drawn over the pixels of the nearest
+    /// non-clipping layer below it.
+    pub fn clip_to_below(&self) -> bool {
+        self.clip_to_below
+    }
+
     /// Returns all the instructions applied to this layer.
     pub fn history(&self) -> &Vec<InstructionBox> {
         &self.history
@@ -168,7 +259,457 @@ impl Layer {
         self.history_index
     }
 
+    /// Renders the layer at its current history index.
+    ///
+    /// Rendering starts from the closest snapshot before the history index.
+    /// Pattern fills use the given patterns of the drawing.
+    pub fn render(
+        &self,
+        width: u32,
+        height: u32,
+        patterns: &[Pattern],
+    ) -> Result<Pixmap, RenderError> {
+        let (start, mut pixmap) = match self.snapshots.range(..=self.history_index).next_back() {
+            Some((index, data)) => (*index, self.snapshot_canvas(data, width, height)?),
+            None => (0, canvas(width, height)?),
+        };
+        for instruction in &self.history[start as usize..self.history_index as usize] {
+            if instruction.applied {
+                instruction.instruction.render(&mut pixmap, patterns)?;
+            }
+        }
+        Ok(pixmap)
+    }
+
+    /// Returns the mask of the layer, if any.
+    pub fn mask(&self) -> Option<&LayerMask> {
+        self.mask.as_deref()
+    }
+
+    /// Returns the mask of the layer, to edit it.
+    pub fn mask_mut(&mut self) -> Result<&mut LayerMask, LayerError> {
+        self.mask.as_deref_mut().ok_or(LayerError::NoMask)
+    }
+
+    /// Adds an empty mask to the layer, which reveals the whole layer.
+    pub fn add_mask(&mut self) -> Result<(), LayerError> {
+        if self.mask.is_some() {
+            return Err(LayerError::MaskAlreadyExists);
+        }
+        self.mask = Some(Box::new(LayerMask::new()));
+        Ok(())
+    }
+
+    /// Removes the mask of the layer, without changing the layer's pixels.
+    pub fn remove_mask(&mut self) -> Result<LayerMask, LayerError> {
+        self.mask.take().map(|mask| *mask).ok_or(LayerError::NoMask)
+    }
+
+    /// Bakes the mask into the layer.
+    ///
+    /// The mask is removed and replaced by an instruction at the end of the
+    /// history, so that applying the mask can be undone like any instruction.
+    /// The new instruction is returned so that its author and creation time can be set.
+    pub fn apply_mask(&mut self, uuid: String) -> Result<&mut InstructionBox, LayerError> {
+        let mask = self.mask.as_ref().ok_or(LayerError::NoMask)?.content();
+        let instructions = mask.history()[..mask.history_index() as usize]
+            .iter()
+            .filter(|i| i.applied)
+            .map(|i| i.instruction.clone())
+            .collect();
+        self.instruct(InstructionBox {
+            instruction: Instruction::ApplyMask(MaskApplication::new(instructions)),
+            applied: true,
+            uuid,
+            author: None,
+            created_at: None,
+        })?;
+        self.mask = None;
+        Ok(self.history.last_mut().unwrap())
+    }
+
+    /// Hides the parts of the rendered layer that the mask hides, if the mask is enabled.
+    pub(crate) fn apply_mask_to(
+        &self,
+        pixmap: &mut Pixmap,
+        patterns: &[Pattern],
+    ) -> Result<(), RenderError> {
+        if let Some(mask) = self.mask().filter(|m| m.is_enabled()) {
+            pixmap.apply_mask(&mask.render(pixmap.width(), pixmap.height(), patterns)?);
+        }
+        Ok(())
+    }
+
+    /// Returns the applied instructions affecting the given point along with
+    /// their history index, from the most recent to the oldest.
+    pub fn instructions_at(
+        &self,
+        point: &Point,
+        width: u32,
+        height: u32,
+        patterns: &[Pattern],
+    ) -> Result<Vec<(u64, &InstructionBox)>, RenderError> {
+        let mut pixmap = self.base(width, height)?;
+        let mut hits = vec![];
+        for (index, instruction) in self.history[..self.history_index as usize]
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.applied)
+        {
+            if instruction.instruction.covers(point, &pixmap, patterns)? {
+                hits.push((index as u64 + 1, instruction));
+            }
+            instruction.instruction.render(&mut pixmap, patterns)?;
+        }
+        hits.reverse();
+        Ok(hits)
+    }
+
+    /// Returns the canvas the history is applied on.
+    ///
+    /// This is empty unless the history has been truncated.
+    pub(crate) fn base(&self, width: u32, height: u32) -> Result<Pixmap, RenderError> {
+        match self.snapshots.get(&0) {
+            Some(data) => self.snapshot_canvas(data, width, height),
+            None => canvas(width, height),
+        }
+    }
+
+    fn snapshot_canvas(&self, data: &str, width: u32, height: u32) -> Result<Pixmap, RenderError> {
+        let snapshot = decode_image(data)?;
+        let mut pixmap = canvas(width, height)?;
+        pixmap.draw_pixmap(
+            0,
+            0,
+            snapshot.as_ref(),
+            &Default::default(),
+            Default::default(),
+            None,
+        );
+        Ok(pixmap)
+    }
+
+    /// Drops the snapshots taken at or after the given history index, which
+    /// include the instruction at that index.
     fn invalidate_snapshots(&mut self, index: u64) {
-        self.snapshots = self.snapshots.split_off(&index);
+        self.snapshots.retain(|snapshot, _| *snapshot < index);
+    }
+}
+
+/// Checks that an instruction can be added to a layer.
+fn validate(instruction: &Instruction) -> Result<(), LayerError> {
+    let stroke = match instruction {
+        Instruction::Stroke(s) => Some(s),
+        Instruction::Smudge(s) => Some(&s.stroke),
+        Instruction::BlurStroke(b) => Some(&b.stroke),
+        Instruction::CloneStamp(c) => Some(&c.stroke),
+        Instruction::SymmetricStroke(s) => Some(&s.stroke),
+        _ => None,
+    };
+    if let Some(stroke) = stroke {
+        if stroke.is_empty() {
+            return Err(LayerError::MinStrokePoints);
+        }
+        if ![0, stroke.len()].contains(&stroke.pressures().len())
+            || ![0, stroke.len()].contains(&stroke.tilts().len())
+        {
+            return Err(LayerError::StylusPointsMismatch);
+        }
+    }
+    let points: Vec<&Point> = match instruction {
+        Instruction::Line(l) => vec![&l.start, &l.end],
+        Instruction::Rectangle(r) => vec![&r.start, &r.end],
+        Instruction::Ellipse(e) => vec![&e.center, &e.radius],
+        Instruction::Polygon(p) => p.points.iter().collect(),
+        _ => stroke
+            .map(|s| s.points().iter().collect())
+            .unwrap_or_default(),
+    };
+    if !points.iter().all(|point| point.is_in_range()) {
+        return Err(LayerError::PointOutOfRange);
+    }
+    let brush = match instruction {
+        Instruction::Stroke(s) => Some(s.brush()),
+        Instruction::Smudge(s) => Some(s.stroke.brush()),
+        Instruction::BlurStroke(b) => Some(b.stroke.brush()),
+        Instruction::CloneStamp(c) => Some(c.stroke.brush()),
+        Instruction::SymmetricStroke(s) => Some(s.stroke.brush()),
+        Instruction::Bucket(b) => Some(b.brush().clone()),
+        Instruction::Line(l) => Some(l.brush.clone()),
+        Instruction::Rectangle(r) => Some(r.brush.clone()),
+        Instruction::Ellipse(e) => Some(e.brush.clone()),
+        Instruction::Polygon(p) => Some(p.brush.clone()),
+        _ => None,
+    };
+    if let Some(brush) = brush {
+        brush.validate()?;
+    }
+    if let Some(clip) = instruction.clip() {
+        clip.validate()?;
+    }
+    let radius = match instruction {
+        Instruction::Filter(Filter {
+            effect: FilterEffect::Blur { radius } | FilterEffect::Sharpen { radius, .. },
+            ..
+        }) => Some(*radius),
+        Instruction::BlurStroke(b) => Some(b.radius),
+        _ => None,
+    };
+    if let Some(radius) = radius {
+        if !(0.0..=MAX_BLUR_RADIUS).contains(&radius) {
+            return Err(LayerError::InvalidBlurRadius(radius));
+        }
+    }
+    match instruction {
+        Instruction::Polygon(p) if p.points.len() < 3 => Err(LayerError::MinPolygonPoints),
+        Instruction::Gradient(g) if g.stops.is_empty() => Err(LayerError::MinGradientStops),
+        Instruction::Gradient(Gradient {
+            area: FillArea::Selection(selection),
+            ..
+        })
+        | Instruction::Filter(Filter {
+            selection: Some(selection),
+            ..
+        }) => Ok(selection.validate()?),
+        Instruction::SymmetricStroke(s) => Ok(s.symmetry.validate()?),
+        Instruction::Motion(m) => Ok(m.selection.validate()?),
+        Instruction::ApplyMask(m) => m.mask.iter().try_for_each(validate),
+        Instruction::Bucket(b) => match b.fill() {
+            BucketFill::Gradient { stops, .. } if stops.is_empty() => {
+                Err(LayerError::MinGradientStops)
+            }
+            BucketFill::Pattern { scale, .. } if !(scale.is_finite() && *scale > 0.0) => {
+                Err(LayerError::InvalidPatternScale(*scale))
+            }
+            _ if b.expand().max(b.gap_closing()) > MAX_BUCKET_GROWTH => Err(
+                LayerError::InvalidBucketGrowth(b.expand().max(b.gap_closing())),
+            ),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+
+    use super::*;
+    use crate::{BlurStroke, Brush, Bucket, Motion, Point, Selection, Stroke};
+
+    const SIZE: u32 = 20;
+
+    fn stroke(x: f32) -> InstructionBox {
+        let brush = Brush {
+            width: 4.0,
+            opacity: u32::MAX,
+            ..Default::default()
+        };
+        InstructionBox {
+            instruction: Instruction::Stroke(Stroke::new(
+                vec![Point::new(x, 2.0), Point::new(x, 18.0)],
+                brush,
+            )),
+            applied: true,
+            uuid: format!("{x}"),
+            author: None,
+            created_at: None,
+        }
+    }
+
+    fn bucket(x: f32) -> InstructionBox {
+        let brush = Brush {
+            opacity: u32::MAX,
+            ..Default::default()
+        };
+        InstructionBox {
+            instruction: Instruction::Bucket(Bucket::new(Point::new(x, 10.0), brush, 0)),
+            applied: true,
+            uuid: format!("bucket {x}"),
+            author: None,
+            created_at: None,
+        }
+    }
+
+    /// Returns the history index of the instructions at the given point.
+    fn hits(layer: &Layer, x: f32, y: f32) -> Vec<u64> {
+        layer
+            .instructions_at(&Point::new(x, y), SIZE, SIZE, &[])
+            .unwrap()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn alpha(pixmap: &Pixmap, x: u32, y: u32) -> u8 {
+        pixmap.pixel(x, y).unwrap().alpha()
+    }
+
+    /// Saves the rendered layer as a snapshot of the given history index.
+    fn take_snapshot(layer: &mut Layer, index: u64) {
+        layer.set_history_index(index).unwrap();
+        let png = layer.render(SIZE, SIZE, &[]).unwrap().encode_png().unwrap();
+        layer.snapshot(index, base64::engine::general_purpose::STANDARD.encode(png));
+    }
+
+    #[test]
+    fn changed_instructions_are_not_rendered_from_snapshots() {
+        let mut layer = Layer::new();
+        layer.instruct(stroke(5.0)).unwrap();
+        layer.instruct(stroke(15.0)).unwrap();
+        take_snapshot(&mut layer, 1);
+        take_snapshot(&mut layer, 2);
+
+        layer.set_instruction_visibility(2, false).unwrap();
+        let pixmap = layer.render(SIZE, SIZE, &[]).unwrap();
+        assert!(alpha(&pixmap, 5, 10) > 0);
+        assert_eq!(alpha(&pixmap, 15, 10), 0);
+
+        layer.set_instruction_visibility(1, false).unwrap();
+        let pixmap = layer.render(SIZE, SIZE, &[]).unwrap();
+        assert_eq!(alpha(&pixmap, 5, 10), 0);
+    }
+
+    #[test]
+    fn overlapping_instructions_are_hit_from_the_most_recent() {
+        let mut layer = Layer::new();
+        layer.instruct(stroke(5.0)).unwrap();
+        layer.instruct(stroke(15.0)).unwrap();
+        layer.instruct(stroke(6.0)).unwrap();
+        assert_eq!(hits(&layer, 5.5, 10.0), vec![3, 1]);
+        assert_eq!(hits(&layer, 15.0, 10.0), vec![2]);
+        assert_eq!(hits(&layer, 10.0, 10.0), Vec::<u64>::new());
+        assert_eq!(hits(&layer, 8.5, 10.0), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn hidden_and_undone_instructions_are_not_hit() {
+        let mut layer = Layer::new();
+        layer.instruct(stroke(5.0)).unwrap();
+        layer.instruct(stroke(5.0)).unwrap();
+        layer.instruct(stroke(5.0)).unwrap();
+        layer.set_instruction_visibility(2, false).unwrap();
+        assert_eq!(hits(&layer, 5.0, 10.0), vec![3, 1]);
+        layer.set_history_index(2).unwrap();
+        assert_eq!(hits(&layer, 5.0, 10.0), vec![1]);
+        layer.set_history_index(0).unwrap();
+        assert_eq!(hits(&layer, 5.0, 10.0), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn instructions_are_hit_on_the_canvas_they_were_drawn_on() {
+        let mut layer = Layer::new();
+        layer.instruct(stroke(10.0)).unwrap();
+        layer.instruct(bucket(3.0)).unwrap();
+        assert_eq!(hits(&layer, 3.0, 10.0), vec![2]);
+        assert_eq!(hits(&layer, 15.0, 10.0), Vec::<u64>::new());
+
+        // The history is replayed over the base snapshot.
+        take_snapshot(&mut layer, 1);
+        let mut truncated = Layer::new();
+        truncated.snapshot(0, layer.snapshots[&1].clone());
+        truncated.instruct(bucket(3.0)).unwrap();
+        assert_eq!(hits(&truncated, 10.0, 10.0), Vec::<u64>::new());
+        assert_eq!(hits(&truncated, 3.0, 10.0), vec![1]);
+        assert_eq!(hits(&truncated, 15.0, 10.0), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn blur_radii_are_bounded() {
+        let filter = |radius: f32| InstructionBox {
+            instruction: Instruction::Filter(Filter {
+                effect: FilterEffect::Sharpen {
+                    radius,
+                    amount: 1.0,
+                },
+                selection: None,
+            }),
+            applied: true,
+            uuid: "filter".to_string(),
+            author: None,
+            created_at: None,
+        };
+        let blur_stroke = |radius: f32| {
+            let Instruction::Stroke(stroke) = stroke(5.0).instruction else {
+                unreachable!()
+            };
+            InstructionBox {
+                instruction: Instruction::BlurStroke(BlurStroke::new(stroke, radius)),
+                applied: true,
+                uuid: "blur stroke".to_string(),
+                author: None,
+                created_at: None,
+            }
+        };
+        let mut layer = Layer::new();
+        for instruction in [filter, blur_stroke] {
+            layer.instruct(instruction(0.0)).unwrap();
+            layer.instruct(instruction(MAX_BLUR_RADIUS)).unwrap();
+            for radius in [-1.0, MAX_BLUR_RADIUS + 1.0, 1e30, f32::INFINITY, f32::NAN] {
+                assert!(matches!(
+                    layer.instruct(instruction(radius)),
+                    Err(LayerError::InvalidBlurRadius(_))
+                ));
+            }
+        }
+        assert_eq!(layer.history().len(), 4);
+
+        // Rendering an unchecked blur stroke fails instead of overflowing.
+        let mut pixmap = canvas(SIZE, SIZE).unwrap();
+        assert!(blur_stroke(1e30)
+            .instruction
+            .render(&mut pixmap, &[])
+            .is_err());
+    }
+
+    #[test]
+    fn mask_instructions_are_validated() {
+        let apply = |x: f32| InstructionBox {
+            instruction: Instruction::ApplyMask(MaskApplication::new(vec![
+                stroke(5.0).instruction,
+                stroke(x).instruction,
+            ])),
+            applied: true,
+            uuid: "mask".to_string(),
+            author: None,
+            created_at: None,
+        };
+        let mut layer = Layer::new();
+        layer.instruct(apply(10.0)).unwrap();
+        assert!(matches!(
+            layer.instruct(apply(f32::NAN)),
+            Err(LayerError::PointOutOfRange)
+        ));
+        assert_eq!(layer.history().len(), 1);
+    }
+
+    #[test]
+    fn selections_are_validated() {
+        let far = Selection::Rectangle {
+            start: Point::new(0.0, 0.0),
+            end: Point::new(f32::NAN, 1.0),
+            rotate: 0,
+        };
+        let Instruction::Stroke(mut clipped) = stroke(5.0).instruction else {
+            unreachable!()
+        };
+        clipped.set_clip(Some(far.clone()));
+        let motion = Motion::new(Point::new(1.0, 1.0), far, Point::new(1.0, 1.0), 0);
+        let mut layer = Layer::new();
+        for instruction in [Instruction::Stroke(clipped), Instruction::Motion(motion)] {
+            let instruction = InstructionBox {
+                instruction,
+                applied: true,
+                uuid: "selection".to_string(),
+                author: None,
+                created_at: None,
+            };
+            assert!(matches!(
+                layer.instruct(instruction),
+                Err(LayerError::InvalidSelection(
+                    SelectionError::PointOutOfRange
+                ))
+            ));
+        }
     }
 }
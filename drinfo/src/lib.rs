@@ -4,12 +4,27 @@ mod drawing;
 mod error;
 mod instructions;
 mod layer;
+mod mask;
+mod palette;
+mod pattern;
 mod point;
+mod render;
+mod selection;
+mod symmetry;
+mod timelapse;
 
 pub use crate::brush::*;
-pub use crate::color::Color;
-pub use crate::drawing::Drawing;
+pub use crate::color::{Color, ColorError};
+pub use crate::drawing::{Drawing, DrawingError, InstructionHit};
 pub use crate::error::Error;
 pub use crate::instructions::*;
 pub use crate::layer::Layer;
-pub use crate::point::Point;
+pub use crate::mask::LayerMask;
+pub use crate::palette::{Palette, PaletteError, Swatch};
+pub use crate::pattern::Pattern;
+pub use crate::point::{Point, MAX_COORDINATE};
+pub use crate::render::RenderError;
+pub use crate::selection::{Selection, SelectionError, SelectionOperation};
+pub use crate::symmetry::{Symmetry, SymmetryError, SymmetryKind, MAX_SYMMETRY_SEGMENTS};
+pub use crate::timelapse::Timelapse;
+pub use tiny_skia::Pixmap;
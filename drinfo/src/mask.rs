@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::Mask;
+
+use crate::{
+    render::{grayscale_mask, RenderError},
+    Layer, Pattern,
+};
+
+/// A grayscale mask controlling which parts of a layer are visible.
+///
+/// The mask is drawn with regular instructions, in its own history, over a
+/// white background. White parts of the layer stay visible, black parts are
+/// hidden and gray parts are partially transparent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayerMask {
+    content: Layer,
+    enabled: bool,
+}
+
+impl Default for LayerMask {
+    fn default() -> Self {
+        Self {
+            content: Layer::new(),
+            enabled: true,
+        }
+    }
+}
+
+impl LayerMask {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the instructions drawing the mask.
+    pub fn content(&self) -> &Layer {
+        &self.content
+    }
+
+    /// Returns the instructions drawing the mask, to edit them.
+    pub fn content_mut(&mut self) -> &mut Layer {
+        &mut self.content
+    }
+
+    /// Returns true if the mask is used when compositing the layer.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enables or disables the mask, without losing its content.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Renders the mask at its current history index.
+    pub fn render(
+        &self,
+        width: u32,
+        height: u32,
+        patterns: &[Pattern],
+    ) -> Result<Mask, RenderError> {
+        grayscale_mask(&self.content.render(width, height, patterns)?)
+    }
+}
@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Color;
+
+/// The colors shared by everyone working on a drawing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Palette {
+    swatches: Vec<Swatch>,
+}
+
+/// A named color of a palette.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Swatch {
+    /// The name of the swatch, unique in its palette.
+    pub name: String,
+    pub color: Color,
+    /// The group the swatch belongs to, if any.
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum PaletteError {
+    #[error("swatch {0} already exists")]
+    SwatchAlreadyExists(String),
+    #[error("could not find swatch {0}")]
+    SwatchNotFound(String),
+    #[error("cannot move a swatch to position {0}")]
+    InvalidPosition(usize),
+    #[error("invalid GIMP palette: {0}")]
+    InvalidGpl(String),
+}
+
+impl Swatch {
+    pub fn new(name: &str, color: Color, group: Option<String>) -> Self {
+        Swatch {
+            name: String::from(name),
+            color,
+            group,
+        }
+    }
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the swatches, in order.
+    pub fn swatches(&self) -> &[Swatch] {
+        &self.swatches
+    }
+
+    /// Returns the swatch with the given name.
+    pub fn swatch(&self, name: &str) -> Option<&Swatch> {
+        self.swatches.iter().find(|s| s.name == name)
+    }
+
+    /// Returns the name of every group, in the order they first appear.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups = vec![];
+        for group in self.swatches.iter().filter_map(|s| s.group.as_deref()) {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    /// Adds a swatch at the end of the palette.
+    ///
+    /// Since names are used as identifiers, this fails if another swatch
+    /// has the same name.
+    pub fn add(&mut self, swatch: Swatch) -> Result<(), PaletteError> {
+        if self.swatch(&swatch.name).is_some() {
+            return Err(PaletteError::SwatchAlreadyExists(swatch.name));
+        }
+        self.swatches.push(swatch);
+        Ok(())
+    }
+
+    /// Removes the swatch with the given name.
+    pub fn remove(&mut self, name: &str) -> Result<Swatch, PaletteError> {
+        let index = self.position(name)?;
+        Ok(self.swatches.remove(index))
+    }
+
+    /// Moves the swatch with the given name to the given position, starting at 0.
+    pub fn move_swatch(&mut self, name: &str, position: usize) -> Result<(), PaletteError> {
+        let index = self.position(name)?;
+        if position >= self.swatches.len() {
+            return Err(PaletteError::InvalidPosition(position));
+        }
+        let swatch = self.swatches.remove(index);
+        self.swatches.insert(position, swatch);
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> Result<usize, PaletteError> {
+        self.swatches
+            .iter()
+            .position(|s| s.name == name)
+            .ok_or_else(|| PaletteError::SwatchNotFound(name.to_string()))
+    }
+
+    /// Reads a GIMP palette (`.gpl`).
+    ///
+    /// Comments written as `# Group: <name>` put the following colors in a group.
+    /// Unnamed colors are named after their hex code and duplicated names get
+    /// a number so that names stay unique.
+    pub fn from_gpl(gpl: &str) -> Result<Self, PaletteError> {
+        let mut lines = gpl.lines();
+        if lines.next().map(str::trim) != Some("GIMP Palette") {
+            return Err(PaletteError::InvalidGpl(String::from(
+                "missing GIMP Palette header",
+            )));
+        }
+        let mut palette = Palette::new();
+        let mut group = None;
+        for line in lines.map(str::trim).filter(|l| !l.is_empty()) {
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some(name) = comment.trim().strip_prefix("Group:") {
+                    let name = name.trim();
+                    group = (!name.is_empty()).then(|| name.to_string());
+                }
+                continue;
+            }
+            if line.starts_with("Name:") || line.starts_with("Columns:") {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let mut channel = || {
+                parts
+                    .next()
+                    .and_then(|c| c.parse::<u8>().ok())
+                    .ok_or_else(|| PaletteError::InvalidGpl(format!("invalid color {line}")))
+            };
+            let color = Color::rgb(channel()?, channel()?, channel()?);
+            let name = parts.collect::<Vec<_>>().join(" ");
+            let name = if name.is_empty() {
+                color.to_hex()
+            } else {
+                name
+            };
+            let mut unique = name.clone();
+            let mut count = 1;
+            while palette.swatch(&unique).is_some() {
+                count += 1;
+                unique = format!("{name} ({count})");
+            }
+            palette.swatches.push(Swatch {
+                name: unique,
+                color,
+                group: group.clone(),
+            });
+        }
+        Ok(palette)
+    }
+
+    /// Writes the palette as a GIMP palette (`.gpl`) with the given name.
+    ///
+    /// Groups are written as `# Group: <name>` comments. GIMP palettes do not
+    /// support transparency, so the alpha of the colors is lost, nor line
+    /// breaks in names, so control characters are replaced by spaces.
+    pub fn to_gpl(&self, name: &str) -> String {
+        let mut gpl = format!("GIMP Palette\nName: {}\n#\n", gpl_text(name));
+        let mut group = None;
+        for swatch in &self.swatches {
+            if swatch.group != group {
+                group = swatch.group.clone();
+                let name = gpl_text(group.as_deref().unwrap_or(""));
+                gpl.push_str(&format!("# Group: {name}\n"));
+            }
+            let Color { r, g, b, .. } = swatch.color;
+            gpl.push_str(&format!("{r:3} {g:3} {b:3}\t{}\n", gpl_text(&swatch.name)));
+        }
+        gpl
+    }
+}
+
+/// Replaces the control characters of a text written in a GIMP palette by spaces.
+fn gpl_text(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpl_round_trip() {
+        let mut palette = Palette::new();
+        for swatch in [
+            Swatch::new("Ink", Color::rgb(0, 0, 0), None),
+            Swatch::new("Sky blue", Color::rgb(135, 206, 235), Some("Sky".into())),
+            Swatch::new("Sunset", Color::rgb(250, 94, 31), Some("Sky".into())),
+            Swatch::new("Paper", Color::rgb(255, 255, 255), Some("Base".into())),
+        ] {
+            palette.add(swatch).unwrap();
+        }
+        let read = Palette::from_gpl(&palette.to_gpl("Test")).unwrap();
+        assert_eq!(read.swatches().len(), palette.swatches().len());
+        for (read, swatch) in read.swatches().iter().zip(palette.swatches()) {
+            assert_eq!(read.name, swatch.name);
+            assert_eq!(read.color, swatch.color);
+            assert_eq!(read.group, swatch.group);
+        }
+        assert_eq!(read.groups(), vec!["Sky", "Base"]);
+    }
+
+    #[test]
+    fn gpl_drops_alpha() {
+        let mut palette = Palette::new();
+        palette
+            .add(Swatch::new("Glass", Color::rgba(10, 20, 30, 40), None))
+            .unwrap();
+        let read = Palette::from_gpl(&palette.to_gpl("Test")).unwrap();
+        assert_eq!(read.swatches()[0].color, Color::rgb(10, 20, 30));
+    }
+
+    #[test]
+    fn gpl_control_characters_stay_on_their_line() {
+        let mut palette = Palette::new();
+        palette
+            .add(Swatch::new(
+                "Red\n  0   0 255\tInjected",
+                Color::rgb(255, 0, 0),
+                Some("Warm\r\n# Group: Cold".into()),
+            ))
+            .unwrap();
+        let gpl = palette.to_gpl("Name\nColumns: 3");
+        assert_eq!(gpl.lines().count(), 5);
+        let read = Palette::from_gpl(&gpl).unwrap();
+        assert_eq!(read.swatches().len(), 1);
+        let swatch = &read.swatches()[0];
+        assert_eq!(swatch.color, Color::rgb(255, 0, 0));
+        assert_eq!(swatch.name, "Red 0 0 255 Injected");
+        assert_eq!(swatch.group.as_deref(), Some("Warm  # Group: Cold"));
+    }
+
+    #[test]
+    fn gpl_names_missing_and_duplicated_colors() {
+        let gpl = "GIMP Palette\nName: Test\nColumns: 2\n#\n\
+                   255 0 0 Red\n0 255 0\n0 0 255 Red\n0 0 0 Red\n";
+        let read = Palette::from_gpl(gpl).unwrap();
+        let names: Vec<_> = read.swatches().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Red", "#00ff00", "Red (2)", "Red (3)"]);
+    }
+
+    #[test]
+    fn gpl_rejects_invalid_palettes() {
+        assert!(Palette::from_gpl("0 0 0 Black\n").is_err());
+        assert!(Palette::from_gpl("GIMP Palette\n0 0 Black\n").is_err());
+        assert!(Palette::from_gpl("GIMP Palette\n256 0 0 Red\n").is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use tiny_skia::Pixmap;
+
+use crate::render::{decode_image, RenderError};
+
+/// An image shared by the pattern fills of a drawing.
+///
+/// Fills refer to the pattern by id, so that the image is only stored and
+/// sent once however many fills use it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pattern {
+    /// The identifier of the pattern, unique in its drawing.
+    pub id: String,
+    /// The image, as a data URL (or as raw base64).
+    pub image: String,
+}
+
+impl Pattern {
+    pub fn new(id: &str, image: String) -> Self {
+        Pattern {
+            id: String::from(id),
+            image,
+        }
+    }
+
+    /// Decodes the image of the pattern.
+    pub(crate) fn decode(&self) -> Result<Pixmap, RenderError> {
+        decode_image(&self.image)
+    }
+}
+
+/// Returns the pattern with the given id.
+pub(crate) fn find<'a>(patterns: &'a [Pattern], id: &str) -> Result<&'a Pattern, RenderError> {
+    patterns
+        .iter()
+        .find(|p| p.id == id)
+        .ok_or_else(|| RenderError::PatternNotFound(id.to_string()))
+}
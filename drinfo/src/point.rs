@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+/// The largest absolute value of a coordinate of the points of an instruction.
+pub const MAX_COORDINATE: f32 = 65536.0;
+
 /// A point in a 2D space.
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct Point {
@@ -11,4 +14,10 @@ impl Point {
     pub fn new(x: f32, y: f32) -> Point {
         Point { x, y }
     }
+
+    /// Returns true if both coordinates are finite and at most
+    /// [`MAX_COORDINATE`] away from 0.
+    pub fn is_in_range(&self) -> bool {
+        self.x.abs() <= MAX_COORDINATE && self.y.abs() <= MAX_COORDINATE
+    }
 }
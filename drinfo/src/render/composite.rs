@@ -0,0 +1,70 @@
+use std::borrow::Cow;
+
+use tiny_skia::{Mask, MaskType, Pixmap};
+
+use super::{canvas, RenderError};
+
+/// The layer that clipping layers are restricted to.
+enum Base<'a> {
+    /// No layer has been stacked yet.
+    None,
+    Hidden,
+    Visible(Cow<'a, Pixmap>),
+}
+
+/// Stacks rendered layers on top of each other, from the bottom to the top.
+pub(crate) struct Compositor<'a> {
+    pixmap: Pixmap,
+    base: Base<'a>,
+}
+
+impl<'a> Compositor<'a> {
+    pub(crate) fn new(width: u32, height: u32) -> Result<Self, RenderError> {
+        Ok(Compositor {
+            pixmap: canvas(width, height)?,
+            base: Base::None,
+        })
+    }
+
+    /// Stacks a layer on top of the previous ones, `None` being a hidden layer.
+    ///
+    /// A clipping layer is only drawn where the nearest non-clipping layer
+    /// below it is, and is hidden along with it. A clipping layer without any
+    /// layer below it is drawn like a regular layer.
+    pub(crate) fn push(&mut self, layer: Option<Cow<'a, Pixmap>>, clip_to_below: bool) {
+        match (&self.base, clip_to_below) {
+            (Base::Hidden, true) => {}
+            (Base::Visible(base), true) => {
+                if let Some(layer) = layer {
+                    let mut clipped = layer.into_owned();
+                    clipped.apply_mask(&Mask::from_pixmap(base.as_ref().as_ref(), MaskType::Alpha));
+                    draw(&mut self.pixmap, &clipped);
+                }
+            }
+            (Base::None, _) | (_, false) => {
+                self.base = match layer {
+                    Some(layer) => {
+                        draw(&mut self.pixmap, &layer);
+                        Base::Visible(layer)
+                    }
+                    None => Base::Hidden,
+                };
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Pixmap {
+        self.pixmap
+    }
+}
+
+fn draw(pixmap: &mut Pixmap, layer: &Pixmap) {
+    pixmap.draw_pixmap(
+        0,
+        0,
+        layer.as_ref(),
+        &Default::default(),
+        Default::default(),
+        None,
+    );
+}
@@ -0,0 +1,449 @@
+//! Rasterization of drawings.
+//!
+//! This mirrors the way toupper renders instructions on an HTML canvas, so
+//! that a drawing can be turned into pixels without a browser.
+
+mod composite;
+
+pub(crate) use self::composite::Compositor;
+
+use ab_glyph::FontRef;
+use base64::Engine;
+use thiserror::Error;
+use tiny_skia::{Mask, MaskType, Path, PathBuilder, Pixmap, PremultipliedColorU8};
+
+use crate::{Color, Point};
+
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("cannot create a {0}x{1} canvas")]
+    InvalidSize(u32, u32),
+    #[error("invalid image data: {0}")]
+    InvalidImage(String),
+    #[error("could not find pattern {0}")]
+    PatternNotFound(String),
+}
+
+/// Converts a `u32` ratio (where [`u32::MAX`] is 1) to a float between 0 and 1.
+pub(crate) fn ratio(value: u32) -> f32 {
+    (value as f64 / u32::MAX as f64) as f32
+}
+
+/// Converts a `u32` rotation (where [`u32::MAX`] is a full turn) to degrees.
+pub(crate) fn degrees(rotate: u32) -> f32 {
+    ratio(rotate) * 360.0
+}
+
+/// Converts a color and a `u32` opacity to a color usable by the rasterizer.
+///
+/// The opacity is multiplied with the alpha of the color.
+pub(crate) fn skia_color(color: &Color, opacity: u32) -> tiny_skia::Color {
+    let mut c = tiny_skia::Color::from_rgba8(color.r, color.g, color.b, color.a);
+    c.apply_opacity(ratio(opacity));
+    c
+}
+
+/// Returns the font used to render text instructions.
+pub(crate) fn font() -> FontRef<'static> {
+    FontRef::try_from_slice(include_bytes!("../../fonts/DejaVuSans.ttf"))
+        .expect("the bundled font is valid")
+}
+
+/// Converts a color and a `u32` opacity to a premultiplied pixel.
+pub(crate) fn premultiplied(color: &Color, opacity: u32) -> PremultipliedColorU8 {
+    skia_color(color, opacity).premultiply().to_color_u8()
+}
+
+/// Creates an empty canvas.
+pub(crate) fn canvas(width: u32, height: u32) -> Result<Pixmap, RenderError> {
+    Pixmap::new(width, height).ok_or(RenderError::InvalidSize(width, height))
+}
+
+/// Decodes an image stored as a data URL (or as raw base64).
+pub(crate) fn decode_image(data: &str) -> Result<Pixmap, RenderError> {
+    let encoded = match data.split_once(',') {
+        Some((header, encoded)) if header.starts_with("data:") => encoded,
+        _ => data,
+    };
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| RenderError::InvalidImage(e.to_string()))?;
+    let image = image::load_from_memory(&bytes)
+        .map_err(|e| RenderError::InvalidImage(e.to_string()))?
+        .into_rgba8();
+    let (width, height) = image.dimensions();
+    let mut pixmap = canvas(width, height)?;
+    for (pixel, rgba) in pixmap.pixels_mut().iter_mut().zip(image.pixels()) {
+        let [r, g, b, a] = rgba.0;
+        *pixel = tiny_skia::ColorU8::from_rgba(r, g, b, a).premultiply();
+    }
+    Ok(pixmap)
+}
+
+/// Encodes an image as a PNG data URL.
+pub(crate) fn encode_image(pixmap: &Pixmap) -> Result<String, RenderError> {
+    let png = pixmap
+        .encode_png()
+        .map_err(|e| RenderError::InvalidImage(e.to_string()))?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png)
+    ))
+}
+
+/// Builds a closed path going through all the given points.
+pub(crate) fn polygon_path(points: &[Point]) -> Option<Path> {
+    let (first, rest) = points.split_first()?;
+    let mut builder = PathBuilder::new();
+    builder.move_to(first.x, first.y);
+    for point in rest {
+        builder.line_to(point.x, point.y);
+    }
+    builder.close();
+    builder.finish()
+}
+
+/// Returns true if the point is inside the polygon (even-odd rule).
+pub(crate) fn polygon_contains(polygon: &[Point], point: &Point) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[j];
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Returns the index of the pixel containing the point, if it is on the canvas.
+pub(crate) fn pixel_index(pixmap: &Pixmap, point: &Point) -> Option<usize> {
+    if point.x < 0.0 || point.y < 0.0 {
+        return None;
+    }
+    let (x, y) = (point.x as u32, point.y as u32);
+    if x < pixmap.width() && y < pixmap.height() {
+        Some((y * pixmap.width() + x) as usize)
+    } else {
+        None
+    }
+}
+
+/// Computes the contiguous region of similar pixels around a point.
+///
+/// The region is made of every pixel connected to the start point whose
+/// channels all differ by at most `tolerance` from the start pixel,
+/// grown by one pixel to cover anti-aliased edges.
+pub(crate) fn flood_region(pixmap: &Pixmap, point: &Point, tolerance: u32) -> Vec<bool> {
+    fill_region(
+        pixmap,
+        point,
+        &RegionOptions {
+            tolerance,
+            ..Default::default()
+        },
+    )
+}
+
+/// How [`fill_region`] selects pixels.
+#[derive(Default)]
+pub(crate) struct RegionOptions {
+    /// How much the channels of a pixel can differ from the start pixel.
+    pub(crate) tolerance: u32,
+    /// Selects every similar pixel of the canvas, connected or not.
+    pub(crate) global: bool,
+    /// How many pixels the region is grown by, besides the pixel always
+    /// added to cover anti-aliased edges.
+    pub(crate) expand: u32,
+    /// The width of the gaps between different pixels that the region does
+    /// not leak through.
+    pub(crate) gap_closing: u32,
+}
+
+/// Computes the region of pixels similar to the pixel at the given point.
+///
+/// Without options, this is the region computed by [`flood_region`].
+///
+/// Gaps are closed by growing the different pixels by half the gap width
+/// before flooding, then growing the flooded region back by as much, over
+/// similar pixels only.
+pub(crate) fn fill_region(pixmap: &Pixmap, point: &Point, options: &RegionOptions) -> Vec<bool> {
+    let (width, height) = (pixmap.width() as usize, pixmap.height() as usize);
+    let Some(start) = pixel_index(pixmap, point) else {
+        return vec![false; width * height];
+    };
+    let pixels = pixmap.pixels();
+    let target = pixels[start].demultiply();
+    let tolerance = ratio(options.tolerance) * 255.0;
+    let similar: Vec<bool> = pixels
+        .iter()
+        .map(|pixel| {
+            let color = pixel.demultiply();
+            [
+                (color.red(), target.red()),
+                (color.green(), target.green()),
+                (color.blue(), target.blue()),
+                (color.alpha(), target.alpha()),
+            ]
+            .iter()
+            .all(|(a, b)| (*a as f32 - *b as f32).abs() <= tolerance)
+        })
+        .collect();
+
+    let filled = if options.global {
+        similar.clone()
+    } else if options.gap_closing > 0 {
+        let half_gap = options.gap_closing.div_ceil(2);
+        let different: Vec<bool> = similar.iter().map(|s| !s).collect();
+        let walls = grow(&different, width, height, half_gap, true);
+        let open: Vec<bool> = similar.iter().zip(&walls).map(|(s, w)| *s && !w).collect();
+        // A start point in a narrow area is filled as if gaps were not closed.
+        let flooded = if open[start] {
+            flood(&open, width, height, start)
+        } else {
+            flood(&similar, width, height, start)
+        };
+        grow(&flooded, width, height, half_gap, true)
+            .into_iter()
+            .zip(&similar)
+            .map(|(f, s)| f && *s)
+            .collect()
+    } else {
+        flood(&similar, width, height, start)
+    };
+    grow(
+        &filled,
+        width,
+        height,
+        options.expand.saturating_add(1),
+        false,
+    )
+}
+
+/// Returns the pixels connected to the start pixel through allowed pixels.
+fn flood(allowed: &[bool], width: usize, height: usize, start: usize) -> Vec<bool> {
+    let mut filled = vec![false; width * height];
+    let mut stack = vec![start];
+    while let Some(index) = stack.pop() {
+        if filled[index] || !allowed[index] {
+            continue;
+        }
+        filled[index] = true;
+        let (x, y) = (index % width, index / width);
+        if x > 0 {
+            stack.push(index - 1);
+        }
+        if x + 1 < width {
+            stack.push(index + 1);
+        }
+        if y > 0 {
+            stack.push(index - width);
+        }
+        if y + 1 < height {
+            stack.push(index + width);
+        }
+    }
+    filled
+}
+
+/// Grows a region by the given number of pixels, horizontally and
+/// vertically, and diagonally too if asked.
+fn grow(region: &[bool], width: usize, height: usize, steps: u32, diagonal: bool) -> Vec<bool> {
+    let mut region = region.to_vec();
+    for _ in 0..steps {
+        let mut grown = region.clone();
+        for (index, _) in region.iter().enumerate().filter(|(_, f)| **f) {
+            let (x, y) = ((index % width) as isize, (index / width) as isize);
+            for (dx, dy) in [
+                (-1, 0),
+                (1, 0),
+                (0, -1),
+                (0, 1),
+                (-1, -1),
+                (1, -1),
+                (-1, 1),
+                (1, 1),
+            ]
+            .into_iter()
+            .take(if diagonal { 8 } else { 4 })
+            {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx >= 0 && ny >= 0 && (nx as usize) < width && (ny as usize) < height {
+                    grown[ny as usize * width + nx as usize] = true;
+                }
+            }
+        }
+        if grown == region {
+            break;
+        }
+        region = grown;
+    }
+    region
+}
+
+/// Converts a drawn mask to a grayscale mask.
+///
+/// The pixels are put over a white background, then their luminance is used
+/// as the coverage of the mask: white reveals, black hides.
+pub(crate) fn grayscale_mask(pixmap: &Pixmap) -> Result<Mask, RenderError> {
+    let mut background = canvas(pixmap.width(), pixmap.height())?;
+    background.fill(tiny_skia::Color::WHITE);
+    background.draw_pixmap(
+        0,
+        0,
+        pixmap.as_ref(),
+        &Default::default(),
+        Default::default(),
+        None,
+    );
+    Ok(Mask::from_pixmap(background.as_ref(), MaskType::Luminance))
+}
+
+/// A small deterministic random number generator (SplitMix64).
+///
+/// Rendering must give the same pixels on every platform, so random effects
+/// use this instead of a system generator.
+pub(crate) struct Random(u64);
+
+impl Random {
+    /// Creates the generator of the given stream, for the given seed.
+    pub(crate) fn new(seed: u64, stream: u64) -> Self {
+        let mut random = Random(seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03));
+        random.next_u64();
+        random
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number between 0 (included) and 1 (excluded).
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 20;
+
+    /// A white canvas with a black vertical line at `x = 10`, open at the given rows.
+    fn line_art(open_rows: &[u32]) -> Pixmap {
+        let mut pixmap = canvas(SIZE, SIZE).unwrap();
+        pixmap.fill(tiny_skia::Color::WHITE);
+        for y in (0..SIZE).filter(|y| !open_rows.contains(y)) {
+            pixmap.pixels_mut()[(y * SIZE + 10) as usize] =
+                tiny_skia::ColorU8::from_rgba(0, 0, 0, 255).premultiply();
+        }
+        pixmap
+    }
+
+    fn filled(region: &[bool], x: u32, y: u32) -> bool {
+        region[(y * SIZE + x) as usize]
+    }
+
+    /// Returns the filled columns of the given row.
+    fn row(region: &[bool], y: u32) -> Vec<u32> {
+        (0..SIZE).filter(|x| filled(region, *x, y)).collect()
+    }
+
+    #[test]
+    fn fill_region_stops_at_different_pixels() {
+        let region = flood_region(&line_art(&[]), &Point::new(2.0, 2.0), 0);
+        for y in 0..SIZE {
+            assert_eq!(row(&region, y), (0..=10).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn fill_region_outside_of_the_canvas_is_empty() {
+        let region = flood_region(&line_art(&[]), &Point::new(-1.0, 2.0), 0);
+        assert!(region.iter().all(|f| !f));
+    }
+
+    #[test]
+    fn fill_region_tolerance() {
+        let mut pixmap = line_art(&[]);
+        pixmap.fill(tiny_skia::Color::from_rgba8(200, 200, 200, 255));
+        for (x, y) in (14..=16).flat_map(|x| (4..=6).map(move |y| (x, y))) {
+            pixmap.pixels_mut()[(y * SIZE + x) as usize] =
+                tiny_skia::ColorU8::from_rgba(210, 200, 200, 255).premultiply();
+        }
+        let point = Point::new(2.0, 2.0);
+        let strict = flood_region(&pixmap, &point, 0);
+        assert!(!filled(&strict, 15, 5));
+        assert!(filled(&strict, 14, 4));
+        let tolerant = flood_region(&pixmap, &point, u32::MAX / 20);
+        assert!(tolerant.iter().all(|f| *f));
+    }
+
+    #[test]
+    fn fill_region_global() {
+        let options = RegionOptions {
+            global: true,
+            ..Default::default()
+        };
+        let region = fill_region(&line_art(&[]), &Point::new(2.0, 2.0), &options);
+        assert!(region.iter().all(|f| *f));
+    }
+
+    #[test]
+    fn fill_region_expand() {
+        let options = RegionOptions {
+            expand: 2,
+            ..Default::default()
+        };
+        let region = fill_region(&line_art(&[]), &Point::new(2.0, 2.0), &options);
+        for y in 0..SIZE {
+            assert_eq!(row(&region, y), (0..=12).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn fill_region_gap_closing() {
+        let pixmap = line_art(&[8, 9, 10, 11]);
+        let point = Point::new(2.0, 2.0);
+        let leaking = flood_region(&pixmap, &point, 0);
+        assert!(filled(&leaking, 15, 15));
+
+        let narrow = RegionOptions {
+            gap_closing: 2,
+            ..Default::default()
+        };
+        assert!(filled(&fill_region(&pixmap, &point, &narrow), 15, 15));
+
+        let closed = RegionOptions {
+            gap_closing: 4,
+            ..Default::default()
+        };
+        let region = fill_region(&pixmap, &point, &closed);
+        assert!(!filled(&region, 15, 15));
+        for y in [0, 5, 15, 19] {
+            assert_eq!(row(&region, y), (0..=10).collect::<Vec<_>>());
+        }
+        // The region does not go further than the gap.
+        for y in 8..=11 {
+            assert!(row(&region, y).iter().all(|x| *x <= 11));
+        }
+    }
+
+    #[test]
+    fn fill_region_gap_closing_from_a_narrow_area() {
+        let options = RegionOptions {
+            gap_closing: 4,
+            ..Default::default()
+        };
+        let region = fill_region(&line_art(&[8, 9, 10, 11]), &Point::new(10.0, 9.0), &options);
+        assert!(filled(&region, 2, 2));
+        assert!(filled(&region, 15, 15));
+    }
+}
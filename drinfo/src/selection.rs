@@ -0,0 +1,490 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tiny_skia::{FillRule, Mask, MaskType, Pixmap, PixmapPaint, Transform};
+
+use crate::{
+    instructions::shape::{ellipse_vertices, rectangle_corners},
+    render::{
+        canvas, decode_image, encode_image, flood_region, pixel_index, polygon_contains,
+        polygon_path, RenderError,
+    },
+    Point, MAX_COORDINATE,
+};
+
+/// A selected part of the canvas.
+///
+/// A polygon is written as the plain sequence of its points.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Selection {
+    /// A rectangle going from a corner to the opposite corner.
+    Rectangle {
+        start: Point,
+        end: Point,
+        /// Rotation of the rectangle around its center. `rotate / u32::MAX * 360` = rotation in degrees.
+        #[serde(default)]
+        rotate: u32,
+    },
+    /// An ellipse with the given X and Y radii.
+    Ellipse {
+        center: Point,
+        radius: Point,
+        /// Rotation of the ellipse around its center. `rotate / u32::MAX * 360` = rotation in degrees.
+        #[serde(default)]
+        rotate: u32,
+    },
+    /// Pixels picked by a magic wand, see [`Selection::magic_wand`].
+    MagicWand {
+        /// The position of the top left pixel of the image on the canvas.
+        x: i32,
+        y: i32,
+        /// The selected pixels, as a PNG data URL. Opaque pixels are selected.
+        region: String,
+    },
+    /// Two selections combined together.
+    Combined {
+        operation: SelectionOperation,
+        first: Box<Selection>,
+        second: Box<Selection>,
+    },
+    /// A polygon, drawn with a lasso, as a sequence of at least 3 points.
+    ///
+    /// The last point is linked to the first.
+    #[serde(untagged)]
+    Polygon(Vec<Point>),
+}
+
+/// How two selections are combined.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionOperation {
+    /// Pixels in either selection.
+    Union,
+    /// Pixels in the first selection but not in the second.
+    Subtract,
+    /// Pixels in both selections.
+    Intersect,
+}
+
+#[derive(Error, Debug)]
+pub enum SelectionError {
+    #[error("invalid selection, polygon must have at least 3 points")]
+    MinPolygonPoints,
+    #[error(
+        "invalid selection, coordinates must be finite and at most {MAX_COORDINATE} away from 0"
+    )]
+    PointOutOfRange,
+}
+
+impl Selection {
+    /// Selects the pixels similar to the pixel under the given point and
+    /// connected to it, like a bucket fill would fill them.
+    ///
+    /// The pixels are captured, later changes to the canvas do not change the
+    /// selection.
+    pub fn magic_wand(pixmap: &Pixmap, point: &Point, tolerance: u32) -> Result<Self, RenderError> {
+        let region = flood_region(pixmap, point, tolerance);
+        let width = pixmap.width() as usize;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+        for (index, _) in region.iter().enumerate().filter(|(_, selected)| **selected) {
+            let (x, y) = (index % width, index / width);
+            (min_x, min_y) = (min_x.min(x), min_y.min(y));
+            (max_x, max_y) = (max_x.max(x), max_y.max(y));
+        }
+        if min_x > max_x {
+            return Ok(Selection::MagicWand {
+                x: 0,
+                y: 0,
+                region: encode_image(&canvas(1, 1)?)?,
+            });
+        }
+        let mut image = canvas((max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32)?;
+        let image_width = image.width() as usize;
+        for (y, row) in region.chunks(width).enumerate().take(max_y + 1).skip(min_y) {
+            for x in (min_x..=max_x).filter(|x| row[*x]) {
+                image.pixels_mut()[(y - min_y) * image_width + x - min_x] =
+                    tiny_skia::ColorU8::from_rgba(0, 0, 0, 255).premultiply();
+            }
+        }
+        Ok(Selection::MagicWand {
+            x: min_x as i32,
+            y: min_y as i32,
+            region: encode_image(&image)?,
+        })
+    }
+
+    /// Returns the pixels in this selection or in the other.
+    pub fn union(self, other: Selection) -> Self {
+        self.combine(SelectionOperation::Union, other)
+    }
+
+    /// Returns the pixels in this selection but not in the other.
+    pub fn subtract(self, other: Selection) -> Self {
+        self.combine(SelectionOperation::Subtract, other)
+    }
+
+    /// Returns the pixels in both this selection and the other.
+    pub fn intersect(self, other: Selection) -> Self {
+        self.combine(SelectionOperation::Intersect, other)
+    }
+
+    fn combine(self, operation: SelectionOperation, other: Selection) -> Self {
+        Selection::Combined {
+            operation,
+            first: Box::new(self),
+            second: Box::new(other),
+        }
+    }
+
+    /// Returns the outline of a rectangle, ellipse or polygon selection.
+    pub fn outline(&self) -> Option<Vec<Point>> {
+        match self {
+            Selection::Rectangle { start, end, rotate } => {
+                Some(rectangle_corners(start, end, *rotate))
+            }
+            Selection::Ellipse {
+                center,
+                radius,
+                rotate,
+            } => Some(ellipse_vertices(center, radius, *rotate)),
+            Selection::Polygon(points) => Some(points.clone()),
+            Selection::MagicWand { .. } | Selection::Combined { .. } => None,
+        }
+    }
+
+    /// Returns true if the given point is selected.
+    pub fn contains(&self, point: &Point) -> bool {
+        match self {
+            Selection::MagicWand { x, y, region } => decode_image(region).is_ok_and(|image| {
+                let point = Point::new(point.x - *x as f32, point.y - *y as f32);
+                pixel_index(&image, &point).is_some_and(|i| image.pixels()[i].alpha() > 0)
+            }),
+            Selection::Combined {
+                operation,
+                first,
+                second,
+            } => match operation {
+                SelectionOperation::Union => first.contains(point) || second.contains(point),
+                SelectionOperation::Subtract => first.contains(point) && !second.contains(point),
+                SelectionOperation::Intersect => first.contains(point) && second.contains(point),
+            },
+            _ => self
+                .outline()
+                .is_some_and(|outline| outline.len() >= 3 && polygon_contains(&outline, point)),
+        }
+    }
+
+    /// Returns the top left and bottom right corners of the smallest
+    /// rectangle containing the selection, if anything can be selected.
+    pub fn bounding_box(&self) -> Option<(Point, Point)> {
+        match self {
+            Selection::MagicWand { x, y, region } => {
+                let image = decode_image(region).ok()?;
+                if image.pixels().iter().all(|p| p.alpha() == 0) {
+                    return None;
+                }
+                let (x, y) = (*x as f32, *y as f32);
+                Some((
+                    Point::new(x, y),
+                    Point::new(x + image.width() as f32, y + image.height() as f32),
+                ))
+            }
+            Selection::Combined {
+                operation,
+                first,
+                second,
+            } => {
+                let (first, second) = (first.bounding_box(), second.bounding_box());
+                match (operation, first, second) {
+                    (SelectionOperation::Subtract, first, _) => first,
+                    (SelectionOperation::Union, Some((a, b)), Some((c, d))) => Some((
+                        Point::new(a.x.min(c.x), a.y.min(c.y)),
+                        Point::new(b.x.max(d.x), b.y.max(d.y)),
+                    )),
+                    (SelectionOperation::Union, first, second) => first.or(second),
+                    (SelectionOperation::Intersect, Some((a, b)), Some((c, d))) => {
+                        let min = Point::new(a.x.max(c.x), a.y.max(c.y));
+                        let max = Point::new(b.x.min(d.x), b.y.min(d.y));
+                        (min.x < max.x && min.y < max.y).then_some((min, max))
+                    }
+                    (SelectionOperation::Intersect, _, _) => None,
+                }
+            }
+            _ => {
+                let outline = self.outline().filter(|outline| outline.len() >= 3)?;
+                let (mut min, mut max) = (outline[0].clone(), outline[0].clone());
+                for point in &outline {
+                    (min.x, min.y) = (min.x.min(point.x), min.y.min(point.y));
+                    (max.x, max.y) = (max.x.max(point.x), max.y.max(point.y));
+                }
+                Some((min, max))
+            }
+        }
+    }
+
+    /// Returns how much every pixel of a canvas of the given size is selected.
+    pub(crate) fn mask(&self, width: u32, height: u32) -> Result<Mask, RenderError> {
+        let mut mask = Mask::new(width, height).ok_or(RenderError::InvalidSize(width, height))?;
+        match self {
+            Selection::MagicWand { x, y, region } => {
+                let mut pixmap = canvas(width, height)?;
+                pixmap.draw_pixmap(
+                    *x,
+                    *y,
+                    decode_image(region)?.as_ref(),
+                    &PixmapPaint::default(),
+                    Transform::identity(),
+                    None,
+                );
+                mask = Mask::from_pixmap(pixmap.as_ref(), MaskType::Alpha);
+            }
+            Selection::Combined {
+                operation,
+                first,
+                second,
+            } => {
+                let second = second.mask(width, height)?;
+                mask = first.mask(width, height)?;
+                for (a, b) in mask.data_mut().iter_mut().zip(second.data()) {
+                    let (a16, b16) = (*a as u16, *b as u16);
+                    *a = match operation {
+                        SelectionOperation::Union => (*a).max(*b),
+                        SelectionOperation::Subtract => (a16 * (255 - b16) / 255) as u8,
+                        SelectionOperation::Intersect => (a16 * b16 / 255) as u8,
+                    };
+                }
+            }
+            _ => {
+                if let Some(path) = self
+                    .outline()
+                    .filter(|outline| outline.len() >= 3)
+                    .and_then(|outline| polygon_path(&outline))
+                {
+                    mask.fill_path(&path, FillRule::EvenOdd, true, Transform::identity());
+                }
+            }
+        }
+        Ok(mask)
+    }
+
+    /// Checks that the selection can be drawn.
+    pub fn validate(&self) -> Result<(), SelectionError> {
+        let in_range = match self {
+            Selection::Rectangle { start, end, .. } => start.is_in_range() && end.is_in_range(),
+            Selection::Ellipse { center, radius, .. } => {
+                center.is_in_range() && radius.is_in_range()
+            }
+            Selection::MagicWand { x, y, .. } => Point::new(*x as f32, *y as f32).is_in_range(),
+            Selection::Polygon(points) => points.iter().all(Point::is_in_range),
+            Selection::Combined { .. } => true,
+        };
+        if !in_range {
+            return Err(SelectionError::PointOutOfRange);
+        }
+        match self {
+            Selection::Polygon(points) if points.len() < 3 => Err(SelectionError::MinPolygonPoints),
+            Selection::Combined { first, second, .. } => {
+                first.validate()?;
+                second.validate()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rectangle(x0: f32, y0: f32, x1: f32, y1: f32) -> Selection {
+        Selection::Rectangle {
+            start: Point::new(x0, y0),
+            end: Point::new(x1, y1),
+            rotate: 0,
+        }
+    }
+
+    fn contains(selection: &Selection, x: f32, y: f32) -> bool {
+        selection.contains(&Point::new(x, y))
+    }
+
+    /// Asserts that the bounding box is the given one, give or take a pixel.
+    fn assert_box(selection: &Selection, expected: [f32; 4]) {
+        let (min, max) = selection.bounding_box().expect("no bounding box");
+        for (value, expected) in [min.x, min.y, max.x, max.y].into_iter().zip(expected) {
+            assert!(
+                (value - expected).abs() < 1.0,
+                "{min:?} {max:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rectangle_selection() {
+        let selection = rectangle(30.0, 40.0, 10.0, 20.0);
+        assert!(contains(&selection, 20.0, 30.0));
+        assert!(!contains(&selection, 5.0, 30.0));
+        assert!(!contains(&selection, 20.0, 45.0));
+        assert_box(&selection, [10.0, 20.0, 30.0, 40.0]);
+
+        let rotated = Selection::Rectangle {
+            start: Point::new(0.0, 0.0),
+            end: Point::new(20.0, 20.0),
+            rotate: u32::MAX / 8,
+        };
+        assert!(contains(&rotated, 10.0, 10.0));
+        assert!(!contains(&rotated, 1.0, 1.0));
+        assert!(contains(&rotated, 10.0, -3.0));
+        assert_box(&rotated, [-4.14, -4.14, 24.14, 24.14]);
+    }
+
+    #[test]
+    fn ellipse_selection() {
+        let selection = Selection::Ellipse {
+            center: Point::new(50.0, 50.0),
+            radius: Point::new(20.0, 10.0),
+            rotate: 0,
+        };
+        assert!(contains(&selection, 50.0, 50.0));
+        assert!(contains(&selection, 68.0, 50.0));
+        assert!(!contains(&selection, 50.0, 68.0));
+        assert!(!contains(&selection, 66.0, 58.0));
+        assert_box(&selection, [30.0, 40.0, 70.0, 60.0]);
+    }
+
+    #[test]
+    fn polygon_selection() {
+        let selection = Selection::Polygon(vec![
+            Point::new(0.0, 0.0),
+            Point::new(40.0, 0.0),
+            Point::new(0.0, 40.0),
+        ]);
+        assert!(contains(&selection, 10.0, 10.0));
+        assert!(!contains(&selection, 30.0, 30.0));
+        assert_box(&selection, [0.0, 0.0, 40.0, 40.0]);
+
+        let line = Selection::Polygon(vec![Point::new(0.0, 0.0), Point::new(40.0, 0.0)]);
+        assert!(!contains(&line, 20.0, 0.0));
+        assert!(line.bounding_box().is_none());
+        assert!(line.validate().is_err());
+        assert!(rectangle(0.0, 0.0, 1.0, 1.0)
+            .union(line)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn combined_selections() {
+        let left = rectangle(0.0, 0.0, 20.0, 20.0);
+        let right = rectangle(10.0, 10.0, 30.0, 30.0);
+
+        let union = left.clone().union(right.clone());
+        assert!(contains(&union, 5.0, 5.0));
+        assert!(contains(&union, 25.0, 25.0));
+        assert!(!contains(&union, 25.0, 5.0));
+        assert_box(&union, [0.0, 0.0, 30.0, 30.0]);
+
+        let subtract = left.clone().subtract(right.clone());
+        assert!(contains(&subtract, 5.0, 5.0));
+        assert!(!contains(&subtract, 15.0, 15.0));
+        assert!(!contains(&subtract, 25.0, 25.0));
+        assert_box(&subtract, [0.0, 0.0, 20.0, 20.0]);
+
+        let intersect = left.clone().intersect(right);
+        assert!(!contains(&intersect, 5.0, 5.0));
+        assert!(contains(&intersect, 15.0, 15.0));
+        assert!(!contains(&intersect, 25.0, 25.0));
+        assert_box(&intersect, [10.0, 10.0, 20.0, 20.0]);
+
+        let disjoint = left.intersect(rectangle(40.0, 40.0, 50.0, 50.0));
+        assert!(!contains(&disjoint, 5.0, 5.0));
+        assert!(disjoint.bounding_box().is_none());
+    }
+
+    #[test]
+    fn magic_wand_selection() {
+        let mut pixmap = canvas(30, 30).unwrap();
+        pixmap.fill(tiny_skia::Color::WHITE);
+        for y in 10..20 {
+            for x in 5..15 {
+                pixmap.pixels_mut()[y * 30 + x] =
+                    tiny_skia::ColorU8::from_rgba(255, 0, 0, 255).premultiply();
+            }
+        }
+        let selection = Selection::magic_wand(&pixmap, &Point::new(10.0, 15.0), 0).unwrap();
+        assert!(contains(&selection, 10.0, 15.0));
+        assert!(contains(&selection, 4.5, 15.0));
+        assert!(!contains(&selection, 25.0, 15.0));
+        assert!(!contains(&selection, 10.0, 25.0));
+        assert_box(&selection, [4.0, 9.0, 16.0, 21.0]);
+
+        let outside = Selection::magic_wand(&pixmap, &Point::new(-1.0, 0.0), 0).unwrap();
+        assert!(!contains(&outside, 0.0, 0.0));
+        assert!(outside.bounding_box().is_none());
+    }
+
+    #[test]
+    fn polygons_are_plain_points() {
+        let json = r#"[{"x":0.0,"y":0.0},{"x":10.0,"y":0.0},{"x":0.0,"y":10.0}]"#;
+        let selection: Selection = serde_json::from_str(json).unwrap();
+        assert!(matches!(&selection, Selection::Polygon(points) if points.len() == 3));
+        assert_eq!(serde_json::to_string(&selection).unwrap(), json);
+
+        let rectangle = serde_json::to_string(&rectangle(0.0, 0.0, 1.0, 1.0)).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&rectangle).unwrap(),
+            Selection::Rectangle { .. }
+        ));
+    }
+
+    #[test]
+    fn out_of_range_points_are_rejected() {
+        let far = MAX_COORDINATE + 1.0;
+        let ellipse = |x: f32, rx: f32| Selection::Ellipse {
+            center: Point::new(x, 0.0),
+            radius: Point::new(rx, 1.0),
+            rotate: 0,
+        };
+        let wand = |x: i32| Selection::MagicWand {
+            x,
+            y: 0,
+            region: String::new(),
+        };
+        let polygon = |x: f32| {
+            Selection::Polygon(vec![
+                Point::new(0.0, 0.0),
+                Point::new(x, 0.0),
+                Point::new(0.0, 1.0),
+            ])
+        };
+        let combined = |second: Selection| Selection::Combined {
+            operation: SelectionOperation::Union,
+            first: Box::new(rectangle(0.0, 0.0, 1.0, 1.0)),
+            second: Box::new(second),
+        };
+        let valid = [
+            rectangle(0.0, 0.0, MAX_COORDINATE, -MAX_COORDINATE),
+            ellipse(MAX_COORDINATE, MAX_COORDINATE),
+            wand(MAX_COORDINATE as i32),
+            polygon(-MAX_COORDINATE),
+            combined(polygon(1.0)),
+        ];
+        for selection in valid {
+            assert!(selection.validate().is_ok(), "{selection:?}");
+        }
+        let invalid = [
+            rectangle(0.0, 0.0, far, 1.0),
+            rectangle(f32::NAN, 0.0, 1.0, 1.0),
+            ellipse(far, 1.0),
+            ellipse(0.0, f32::INFINITY),
+            wand(i32::MIN),
+            polygon(far),
+            combined(ellipse(0.0, -far)),
+            combined(combined(polygon(f32::NAN))),
+        ];
+        for selection in invalid {
+            assert!(
+                matches!(selection.validate(), Err(SelectionError::PointOutOfRange)),
+                "{selection:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Point;
+
+/// How drawings are mirrored around a center point.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Symmetry {
+    /// The point the axes go through, or the center of the rotations.
+    pub center: Point,
+    pub kind: SymmetryKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SymmetryKind {
+    /// Mirrored across the vertical axis, left and right.
+    Vertical,
+    /// Mirrored across the horizontal axis, top and bottom.
+    Horizontal,
+    /// Mirrored across both axes, in four quarters.
+    Both,
+    /// Repeated by rotating the given number of times around the center,
+    /// like the segments of a mandala.
+    Radial { segments: u32 },
+}
+
+/// The largest number of segments of a radial symmetry.
+pub const MAX_SYMMETRY_SEGMENTS: u32 = 64;
+
+#[derive(Error, Debug)]
+pub enum SymmetryError {
+    #[error("invalid symmetry with {0} segments, must have between 1 and {MAX_SYMMETRY_SEGMENTS}")]
+    InvalidSegments(u32),
+    #[error("invalid symmetry center")]
+    InvalidCenter,
+}
+
+impl Symmetry {
+    pub fn new(center: Point, kind: SymmetryKind) -> Self {
+        Symmetry { center, kind }
+    }
+
+    /// Returns how many times a drawing appears, the original included.
+    pub fn copies(&self) -> usize {
+        match self.kind {
+            SymmetryKind::Vertical | SymmetryKind::Horizontal => 2,
+            SymmetryKind::Both => 4,
+            SymmetryKind::Radial { segments } => segments.max(1) as usize,
+        }
+    }
+
+    /// Returns where the given point appears in every copy, the original
+    /// point first.
+    pub fn images(&self, point: &Point) -> Vec<Point> {
+        (0..self.copies())
+            .map(|copy| self.image(point, copy))
+            .collect()
+    }
+
+    /// Returns where the given point appears in the given copy, 0 being the original.
+    pub fn image(&self, point: &Point, copy: usize) -> Point {
+        let Point { x: cx, y: cy } = self.center;
+        let (mirror_x, mirror_y) = (2.0 * cx - point.x, 2.0 * cy - point.y);
+        match (&self.kind, copy) {
+            (_, 0) => point.clone(),
+            (SymmetryKind::Vertical, _) | (SymmetryKind::Both, 1) => Point::new(mirror_x, point.y),
+            (SymmetryKind::Horizontal, _) | (SymmetryKind::Both, 2) => {
+                Point::new(point.x, mirror_y)
+            }
+            (SymmetryKind::Both, _) => Point::new(mirror_x, mirror_y),
+            (SymmetryKind::Radial { segments }, copy) => {
+                let angle = std::f32::consts::TAU * copy as f32 / (*segments).max(1) as f32;
+                let (sin, cos) = angle.sin_cos();
+                let (dx, dy) = (point.x - cx, point.y - cy);
+                Point::new(cx + dx * cos - dy * sin, cy + dx * sin + dy * cos)
+            }
+        }
+    }
+
+    /// Checks that the symmetry can be drawn.
+    pub fn validate(&self) -> Result<(), SymmetryError> {
+        if !self.center.x.is_finite() || !self.center.y.is_finite() {
+            return Err(SymmetryError::InvalidCenter);
+        }
+        match self.kind {
+            SymmetryKind::Radial { segments }
+                if !(1..=MAX_SYMMETRY_SEGMENTS).contains(&segments) =>
+            {
+                Err(SymmetryError::InvalidSegments(segments))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn images(kind: SymmetryKind, center: (f32, f32), point: (f32, f32)) -> Vec<(f32, f32)> {
+        Symmetry::new(Point::new(center.0, center.1), kind)
+            .images(&Point::new(point.0, point.1))
+            .into_iter()
+            .map(|p| {
+                (
+                    (p.x * 1000.0).round() / 1000.0,
+                    (p.y * 1000.0).round() / 1000.0,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn points_are_mirrored_across_the_axes() {
+        let (center, point) = ((10.0, 20.0), (3.0, 5.0));
+        assert_eq!(
+            images(SymmetryKind::Vertical, center, point),
+            [(3.0, 5.0), (17.0, 5.0)]
+        );
+        assert_eq!(
+            images(SymmetryKind::Horizontal, center, point),
+            [(3.0, 5.0), (3.0, 35.0)]
+        );
+        assert_eq!(
+            images(SymmetryKind::Both, center, point),
+            [(3.0, 5.0), (17.0, 5.0), (3.0, 35.0), (17.0, 35.0)]
+        );
+    }
+
+    #[test]
+    fn points_are_rotated_around_the_center() {
+        assert_eq!(
+            images(
+                SymmetryKind::Radial { segments: 4 },
+                (10.0, 10.0),
+                (12.0, 10.0)
+            ),
+            [(12.0, 10.0), (10.0, 12.0), (8.0, 10.0), (10.0, 8.0)]
+        );
+        assert_eq!(
+            images(SymmetryKind::Radial { segments: 1 }, (0.0, 0.0), (1.0, 2.0)),
+            [(1.0, 2.0)]
+        );
+    }
+
+    #[test]
+    fn segments_and_centers_are_validated() {
+        let radial =
+            |segments| Symmetry::new(Point::new(0.0, 0.0), SymmetryKind::Radial { segments });
+        assert!(radial(1).validate().is_ok());
+        assert!(radial(MAX_SYMMETRY_SEGMENTS).validate().is_ok());
+        for segments in [0, MAX_SYMMETRY_SEGMENTS + 1, u32::MAX] {
+            assert!(matches!(
+                radial(segments).validate(),
+                Err(SymmetryError::InvalidSegments(_))
+            ));
+        }
+        let center = Symmetry::new(Point::new(f32::NAN, 0.0), SymmetryKind::Both);
+        assert!(matches!(
+            center.validate(),
+            Err(SymmetryError::InvalidCenter)
+        ));
+    }
+}
@@ -0,0 +1,99 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use tiny_skia::{Mask, Pixmap};
+
+use crate::{
+    render::{Compositor, RenderError},
+    Drawing, InstructionBox,
+};
+
+/// Replays the construction of a drawing, one instruction at a time.
+///
+/// Each item is the whole drawing right after an instruction was applied.
+/// Instructions are replayed in the order given by [`Drawing::construction_order`].
+/// Layer masks are used as they are at the end of the drawing.
+pub struct Timelapse<'a> {
+    drawing: &'a Drawing,
+    /// Every instruction to replay along with its layer.
+    steps: Vec<(&'a str, &'a InstructionBox)>,
+    canvases: HashMap<&'a str, Pixmap>,
+    masks: HashMap<&'a str, Mask>,
+    next: usize,
+}
+
+impl<'a> Timelapse<'a> {
+    pub(crate) fn new(drawing: &'a Drawing) -> Result<Self, RenderError> {
+        let mut canvases = HashMap::new();
+        let mut masks = HashMap::new();
+        for name in drawing.layer_order() {
+            if let Some(layer) = drawing.layer(name) {
+                canvases.insert(
+                    name.as_str(),
+                    layer.base(drawing.width(), drawing.height())?,
+                );
+                if let Some(mask) = layer.mask().filter(|m| m.is_enabled()) {
+                    masks.insert(
+                        name.as_str(),
+                        mask.render(drawing.width(), drawing.height(), drawing.patterns())?,
+                    );
+                }
+            }
+        }
+        Ok(Timelapse {
+            drawing,
+            steps: drawing.construction_order(),
+            canvases,
+            masks,
+            next: 0,
+        })
+    }
+
+    fn frame(&mut self) -> Result<Pixmap, RenderError> {
+        let (name, instruction) = self.steps[self.next];
+        let layer_canvas = self.canvases.get_mut(name).unwrap();
+        instruction
+            .instruction
+            .render(layer_canvas, self.drawing.patterns())?;
+
+        let mut compositor = Compositor::new(self.drawing.width(), self.drawing.height())?;
+        for name in self.drawing.layer_order() {
+            let Some(layer) = self.drawing.layer(name) else {
+                continue;
+            };
+            let layer_canvas = self
+                .canvases
+                .get(name.as_str())
+                .filter(|_| layer.is_visible())
+                .map(|layer_canvas| match self.masks.get(name.as_str()) {
+                    Some(mask) => {
+                        let mut masked = layer_canvas.clone();
+                        masked.apply_mask(mask);
+                        Cow::Owned(masked)
+                    }
+                    None => Cow::Borrowed(layer_canvas),
+                });
+            compositor.push(layer_canvas, layer.clip_to_below());
+        }
+        Ok(compositor.finish())
+    }
+}
+
+impl Iterator for Timelapse<'_> {
+    type Item = Result<Pixmap, RenderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.steps.len() {
+            return None;
+        }
+        let frame = self.frame();
+        self.next += 1;
+        Some(frame)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.steps.len() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Timelapse<'_> {}
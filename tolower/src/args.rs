@@ -1,4 +1,6 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::timelapse::TimelapseOptions;
 
 /// A web drawing software to draw with your friends
 #[derive(Parser, Debug, Clone)]
@@ -19,4 +21,31 @@ pub struct Args {
     /// Width of the drawing, ignored if file is present
     #[clap(short, long, default_value_t = 1920)]
     pub width: u32,
+
+    /// Play back how the drawing from the file was made to connected users, from an empty canvas
+    #[clap(long, requires = "file")]
+    pub replay: bool,
+
+    /// Token required to use the administration routes, which are disabled if absent
+    #[clap(long)]
+    pub admin_token: Option<String>,
+
+    /// JSON file containing the default brush presets, offered in addition to the drawing's own
+    #[clap(long)]
+    pub brushes: Option<String>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Export an animation of how the drawing was made instead of starting the server
+    Timelapse {
+        /// Path of the exported animation
+        output: String,
+
+        #[clap(flatten)]
+        options: TimelapseOptions,
+    },
 }
@@ -1,6 +1,6 @@
 #![allow(unused)]
 use axum::extract::ws::WebSocket;
-use axum::routing::{any, get};
+use axum::routing::{any, get, post};
 use axum::Router;
 use clap::Parser;
 use futures::stream::SplitSink;
@@ -10,15 +10,23 @@ use std::io::Write;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use drawing::Drawing;
+use drawing::{BrushPreset, Drawing};
 
 mod args;
+mod replay;
 mod routes;
+mod timelapse;
 mod ws;
 
+pub type UserSender = Arc<Mutex<SplitSink<WebSocket, axum::extract::ws::Message>>>;
+
 pub struct AppData {
     pub drawing: Mutex<Drawing>,
-    pub users: Mutex<HashMap<String, Arc<Mutex<SplitSink<WebSocket, axum::extract::ws::Message>>>>>,
+    pub users: Mutex<HashMap<String, UserSender>>,
+    pub admin_token: Option<String>,
+    pub replay: Option<replay::Replay>,
+    /// Brush presets offered in every drawing, which cannot be edited.
+    pub default_brushes: Vec<BrushPreset>,
 }
 
 #[tokio::main]
@@ -32,15 +40,52 @@ async fn main() -> std::io::Result<()> {
     } else {
         Drawing::new(args.height, args.width)
     };
+    if let Some(args::Command::Timelapse { output, options }) = &args.command {
+        let data = timelapse::export(&drawing, options).expect("Could not export timelapse.");
+        std::fs::write(output, data)?;
+        info!("Timelapse exported to {output}");
+        return Ok(());
+    }
+    let (drawing, replay) = if args.replay {
+        let replay = replay::Replay::new(drawing);
+        let empty = replay
+            .playback
+            .lock()
+            .await
+            .drawing_at(0)
+            .expect("Could not replay drawing.");
+        (empty, Some(replay))
+    } else {
+        (drawing, None)
+    };
+    let default_brushes = if let Some(file) = &args.brushes {
+        let f = std::fs::File::open(file).expect("Brush presets file not found.");
+        serde_json::from_reader(f).expect("Invalid brush presets file.")
+    } else {
+        vec![]
+    };
     let drawing = Mutex::new(drawing);
     let app_data = Arc::new(AppData {
         drawing,
         users: Default::default(),
+        admin_token: args.admin_token.clone(),
+        replay,
+        default_brushes,
     });
+    if app_data.replay.is_some() {
+        tokio::spawn(replay::run(app_data.clone()));
+    }
     info!("Starting server on port {port}");
     let app = Router::new()
         .route("/ws/{username}", any(routes::ws::ws_handler))
         .route("/save", get(routes::pages::save))
+        .route("/instructions_at", get(routes::pages::instructions_at))
+        .route("/timelapse", get(routes::pages::timelapse))
+        .route(
+            "/palette",
+            get(routes::pages::export_palette).post(routes::pages::import_palette),
+        )
+        .route("/admin/revert/{username}", post(routes::admin::revert_user))
         .with_state(app_data);
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
         .await
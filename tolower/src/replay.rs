@@ -0,0 +1,288 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::extract::ws::Message;
+use drawing::{Drawing, DrawingError, Instruction, InstructionBox};
+use futures::SinkExt as _;
+use log::*;
+use tokio::sync::{Mutex, Notify};
+
+use crate::{
+    ws::messages::{
+        InitData, InstructionData, ReplayStateData, TempDrawServerData, WebSocketServerMessage,
+    },
+    AppData, UserSender,
+};
+
+/// Delay between two instructions when their creation time is unknown.
+const DEFAULT_DELAY: Duration = Duration::from_millis(500);
+/// Maximum delay between two instructions at normal speed.
+const MAX_DELAY: Duration = Duration::from_secs(2);
+/// Delay between two stroke segments at normal speed.
+const SEGMENT_DELAY: Duration = Duration::from_millis(16);
+/// Maximum duration of a stroke at normal speed.
+const MAX_STROKE_DURATION: Duration = Duration::from_secs(2);
+
+/// A drawing being played back to the connected users.
+pub struct Replay {
+    pub playback: Mutex<Playback>,
+    /// Wakes the playback task up when the playback state changes.
+    changed: Notify,
+}
+
+impl Replay {
+    pub fn new(source: Drawing) -> Self {
+        Replay {
+            playback: Mutex::new(Playback::new(source)),
+            changed: Notify::new(),
+        }
+    }
+}
+
+/// The playback state of a replay.
+pub struct Playback {
+    source: Drawing,
+    /// Every applied instruction of the source, in the order they were made.
+    steps: Vec<(String, InstructionBox)>,
+    /// Number of instructions already played.
+    pub position: usize,
+    pub playing: bool,
+    /// Speed multiplier, 1 is the original speed.
+    pub speed: f32,
+}
+
+impl Playback {
+    pub fn new(source: Drawing) -> Self {
+        let steps = source
+            .construction_order()
+            .into_iter()
+            .map(|(layer, instruction)| (layer.to_string(), instruction.clone()))
+            .collect();
+        Playback {
+            source,
+            steps,
+            position: 0,
+            playing: false,
+            speed: 1.0,
+        }
+    }
+
+    /// Builds the drawing as it was after the given number of instructions.
+    ///
+    /// Instructions that cannot be replayed are logged and skipped.
+    pub fn drawing_at(&self, position: usize) -> Result<Drawing, DrawingError> {
+        let mut drawing = Drawing::new(self.source.height(), self.source.width());
+        drawing.set_palette(self.source.palette().clone());
+        drawing.set_symmetry(self.source.symmetry().cloned())?;
+        for preset in self.source.brush_presets() {
+            drawing.add_brush_preset(preset.clone())?;
+        }
+        for pattern in self.source.patterns() {
+            drawing.add_pattern(pattern.clone())?;
+        }
+        for name in self.source.layer_order() {
+            drawing.add_layer(name.clone())?;
+            if let Some(layer) = self.source.layer(name) {
+                drawing.set_visibility(name, layer.is_visible())?;
+                drawing.set_clip_to_below(name, layer.clip_to_below())?;
+                if let Some(mask) = layer.mask() {
+                    drawing.add_mask(name)?;
+                    for instruction in mask.content().history() {
+                        drawing.mask_instruct(name, instruction.clone())?;
+                    }
+                    drawing.set_mask_history_index(name, mask.content().history_index())?;
+                    drawing.set_mask_enabled(name, mask.is_enabled())?;
+                }
+            }
+        }
+        for (layer, instruction) in &self.steps[..position.min(self.steps.len())] {
+            if let Err(e) = drawing.instruct(layer, instruction.clone()) {
+                error!("Could not replay instruction {}: {e}", instruction.uuid);
+            }
+        }
+        Ok(drawing)
+    }
+
+    pub fn state(&self) -> ReplayStateData {
+        ReplayStateData {
+            playing: self.playing,
+            position: self.position as u64,
+            length: self.steps.len() as u64,
+            speed: self.speed,
+        }
+    }
+
+    /// Returns how long to wait before playing the next instruction.
+    fn delay(&self) -> Duration {
+        let previous = self.position.checked_sub(1).map(|p| &self.steps[p].1);
+        let next = self.steps.get(self.position).map(|s| &s.1);
+        let delay = match (
+            previous.and_then(|p| p.created_at),
+            next.and_then(|n| n.created_at),
+        ) {
+            (Some(previous), Some(next)) => {
+                Duration::from_millis(next.saturating_sub(previous).max(0) as u64).min(MAX_DELAY)
+            }
+            _ => DEFAULT_DELAY,
+        };
+        delay.div_f32(self.speed)
+    }
+}
+
+/// Sends a message to every connected user.
+pub async fn broadcast(app_data: &AppData, message: &WebSocketServerMessage) {
+    send_all(&mut *app_data.users.lock().await, message).await;
+}
+
+/// Sends a message to the given users.
+///
+/// Locking the users before releasing the playback keeps the messages of
+/// the replay in order with the messages of a seek.
+async fn send_all(users: &mut HashMap<String, UserSender>, message: &WebSocketServerMessage) {
+    let msg = Message::text(serde_json::to_string(message).unwrap());
+    for user in users.values_mut() {
+        user.lock().await.send(msg.clone()).await;
+    }
+}
+
+/// Moves the replay to the given position and resets every user's drawing accordingly.
+pub async fn seek(app_data: &AppData, position: u64) {
+    let Some(replay) = &app_data.replay else {
+        return;
+    };
+    let mut playback = replay.playback.lock().await;
+    let position = (position as usize).min(playback.steps.len());
+    let drawing = match playback.drawing_at(position) {
+        Ok(drawing) => drawing,
+        Err(e) => {
+            error!("Could not seek the replay to {position}: {e}");
+            return;
+        }
+    };
+    playback.position = position;
+    *app_data.drawing.lock().await = drawing.clone();
+    let state = playback.state();
+    let mut users = app_data.users.lock().await;
+    drop(playback);
+    let names = users.keys().cloned().collect();
+    send_all(
+        &mut users,
+        &WebSocketServerMessage::Init(InitData {
+            drawing,
+            users: names,
+        }),
+    )
+    .await;
+    send_all(&mut users, &WebSocketServerMessage::ReplayState(state)).await;
+    replay.changed.notify_one();
+}
+
+/// Updates the playback state and notifies every user.
+pub async fn update(app_data: &AppData, update: impl FnOnce(&mut Playback)) {
+    let Some(replay) = &app_data.replay else {
+        return;
+    };
+    let mut playback = replay.playback.lock().await;
+    update(&mut playback);
+    let state = playback.state();
+    let mut users = app_data.users.lock().await;
+    drop(playback);
+    send_all(&mut users, &WebSocketServerMessage::ReplayState(state)).await;
+    replay.changed.notify_one();
+}
+
+/// Returns true if the playback is still playing the instruction at the given position.
+async fn still_playing(replay: &Replay, position: usize) -> bool {
+    let playback = replay.playback.lock().await;
+    playback.playing && playback.position == position
+}
+
+/// Plays the replay back, forever.
+///
+/// The playback is only locked while checking and changing it, so that it
+/// can be paused or moved while strokes are drawn.
+pub async fn run(app_data: Arc<AppData>) {
+    let Some(replay) = &app_data.replay else {
+        return;
+    };
+    'replay: loop {
+        let step = {
+            let playback = replay.playback.lock().await;
+            if playback.playing && playback.position < playback.steps.len() {
+                Some((
+                    playback.delay(),
+                    playback.position,
+                    playback.steps[playback.position].clone(),
+                    playback.speed,
+                ))
+            } else {
+                None
+            }
+        };
+        let Some((delay, position, (layer, instruction), speed)) = step else {
+            replay.changed.notified().await;
+            continue;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = replay.changed.notified() => continue,
+        }
+
+        if let Instruction::Stroke(stroke) = &instruction.instruction {
+            let segments = stroke.len().saturating_sub(1).max(1) as u32;
+            let segment_delay = SEGMENT_DELAY
+                .min(MAX_STROKE_DURATION / segments)
+                .div_f32(speed);
+            for (index, segment) in stroke.points().windows(2).enumerate() {
+                if !still_playing(replay, position).await {
+                    continue 'replay;
+                }
+                broadcast(
+                    &app_data,
+                    &WebSocketServerMessage::TempDraw(TempDrawServerData {
+                        brush: stroke.brush(),
+                        uuid: instruction.uuid.clone(),
+                        start: segment[0].clone(),
+                        end: segment[1].clone(),
+                        start_pressure: stroke.pressures().get(index).copied(),
+                        end_pressure: stroke.pressures().get(index + 1).copied(),
+                        start_tilt: stroke.tilts().get(index).copied(),
+                        end_tilt: stroke.tilts().get(index + 1).copied(),
+                        seed: stroke.seed(),
+                        clip: stroke.clip().cloned(),
+                        layer: layer.clone(),
+                        username: instruction
+                            .author
+                            .clone()
+                            .unwrap_or_else(|| String::from("replay")),
+                    }),
+                )
+                .await;
+                tokio::time::sleep(segment_delay).await;
+            }
+        }
+
+        let mut playback = replay.playback.lock().await;
+        if !playback.playing || playback.position != position {
+            // The playback changed while the instruction was played.
+            continue;
+        }
+        if let Err(e) = app_data
+            .drawing
+            .lock()
+            .await
+            .instruct(&layer, instruction.clone())
+        {
+            error!("Could not replay instruction {}: {e}", instruction.uuid);
+        }
+        playback.position += 1;
+        let state = playback.state();
+        let mut users = app_data.users.lock().await;
+        drop(playback);
+        send_all(
+            &mut users,
+            &WebSocketServerMessage::Instruction(InstructionData { layer, instruction }),
+        )
+        .await;
+        send_all(&mut users, &WebSocketServerMessage::ReplayState(state)).await;
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{ws::Message, Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use futures::SinkExt as _;
+use log::*;
+use serde::Deserialize;
+
+use crate::{
+    ws::messages::{SetInstructionVisibilityData, WebSocketServerMessage},
+    AppData,
+};
+
+#[derive(Deserialize)]
+pub struct AdminQuery {
+    pub token: String,
+}
+
+fn authorize(data: &AppData, query: &AdminQuery) -> Result<(), StatusCode> {
+    match &data.admin_token {
+        Some(token) if token == &query.token => Ok(()),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+/// Hides every instruction made by the given user and notifies all participants.
+pub async fn revert_user(
+    State(data): State<Arc<AppData>>,
+    Path(username): Path<String>,
+    Query(query): Query<AdminQuery>,
+) -> Result<Json<Vec<SetInstructionVisibilityData>>, StatusCode> {
+    authorize(&data, &query)?;
+    let reverted = data
+        .drawing
+        .lock()
+        .await
+        .revert_author(&username)
+        .map_err(|e| {
+            error!("Could not revert {username}: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    info!("Reverted {} instructions from {username}", reverted.len());
+    let reverted: Vec<SetInstructionVisibilityData> = reverted
+        .into_iter()
+        .map(|(layer, index)| SetInstructionVisibilityData {
+            layer,
+            index,
+            visible: false,
+        })
+        .collect();
+    if !reverted.is_empty() {
+        let msg = Message::text(
+            serde_json::to_string(&WebSocketServerMessage::SetInstructionsVisibility(
+                reverted.clone(),
+            ))
+            .unwrap(),
+        );
+        let mut users = data.users.lock().await;
+        for user in users.values_mut() {
+            user.lock().await.send(msg.clone()).await;
+        }
+    }
+    Ok(Json(reverted))
+}
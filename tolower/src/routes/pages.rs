@@ -1,20 +1,27 @@
 use std::{path::PathBuf, sync::Arc};
 
 use axum::{
-    extract::State,
-    http::{header, HeaderMap, HeaderValue},
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
+    Json,
 };
+use drawing::{InstructionHit, Palette, Point};
+use log::*;
 
-use crate::AppData;
+use crate::{
+    replay,
+    timelapse::{self, TimelapseError, TimelapseOptions},
+    ws::messages::WebSocketServerMessage,
+    AppData,
+};
 
 pub async fn save(State(data): State<Arc<AppData>>) -> impl IntoResponse {
     let headers = HeaderMap::from_iter(
         vec![(
             header::CONTENT_DISPOSITION,
             HeaderValue::from_static("attachment; filename=\"drawing.drinfo\""),
-        )]
-        .into_iter(),
+        )],
     );
     (headers, save_drawing(&*data.drawing.lock().await))
 }
@@ -24,3 +31,86 @@ fn save_drawing(drawing: &drawing::Drawing) -> Vec<u8> {
     ciborium::ser::into_writer(drawing, &mut test).unwrap();
     test
 }
+
+pub async fn instructions_at(
+    State(data): State<Arc<AppData>>,
+    Query(point): Query<Point>,
+) -> Result<Json<Vec<InstructionHit>>, StatusCode> {
+    let drawing = data.drawing.lock().await.clone();
+    tokio::task::spawn_blocking(move || drawing.instructions_at(&point))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .map_err(|e| {
+            error!("Could not find instructions: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+pub async fn timelapse(
+    State(data): State<Arc<AppData>>,
+    Query(options): Query<TimelapseOptions>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let drawing = data.drawing.lock().await.clone();
+    let format = options.format;
+    let animation = tokio::task::spawn_blocking(move || timelapse::export(&drawing, &options))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| match e {
+            TimelapseError::InvalidSize(..) | TimelapseError::InvalidFramesPerInstruction(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            e => {
+                error!("Could not export timelapse: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+    let headers = HeaderMap::from_iter([
+        (
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_str(&format!(
+                "attachment; filename=\"timelapse.{}\"",
+                format.extension()
+            ))
+            .unwrap(),
+        ),
+        (
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(format.mime_type()),
+        ),
+    ]);
+    Ok((headers, animation))
+}
+
+pub async fn export_palette(State(data): State<Arc<AppData>>) -> impl IntoResponse {
+    let headers = HeaderMap::from_iter([
+        (
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_static("attachment; filename=\"palette.gpl\""),
+        ),
+        (
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        ),
+    ]);
+    (headers, data.drawing.lock().await.palette().to_gpl("drawing"))
+}
+
+/// Replaces the palette of the drawing with the uploaded GIMP palette.
+///
+/// Like the swatch messages, this is open to every participant.
+pub async fn import_palette(
+    State(data): State<Arc<AppData>>,
+    gpl: String,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if data.replay.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            String::from("cannot edit the drawing during a replay"),
+        ));
+    }
+    let palette = Palette::from_gpl(&gpl).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    data.drawing.lock().await.set_palette(palette.clone());
+    replay::broadcast(&data, &WebSocketServerMessage::SetPalette(palette)).await;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -7,14 +7,15 @@ use axum::{
     },
     response::IntoResponse,
 };
+use chrono::Utc;
 use drawing::Drawing;
 use futures::{SinkExt as _, StreamExt as _};
 use log::*;
 use tokio::{sync::Mutex, time::timeout};
 
 use crate::{
-    AppData, ws::messages::{
-        CursorServerData, InitData, MoveServerData, MoveStartServerData, SelectionServerData, TempDrawServerData, TempImageServerData, TempImageStartServerData, WebSocketClientMessage, WebSocketServerMessage
+    replay, AppData, ws::messages::{
+        BrushPresetsData, CursorServerData, InitData, InstructionData, InstructionsAtData, MoveServerData, MoveStartServerData, SelectionServerData, TempDrawServerData, TempImageServerData, TempImageStartServerData, WebSocketClientMessage, WebSocketServerMessage
     }
 };
 
@@ -53,15 +54,37 @@ pub async fn handle_socket(socket: WebSocket, username: String, app_data: Arc<Ap
         };
         debug!("Incomming websocket message from {username}: {text}");
         if let Ok(m) = serde_json::from_str::<WebSocketClientMessage>(text) {
+            if app_data.replay.is_some() && m.is_edit() {
+                debug!("Ignoring edit from {username} during replay");
+                continue;
+            }
             match m {
-                WebSocketClientMessage::Instruction(data) => {
-                    if app_data
+                WebSocketClientMessage::Instruction(mut data) => {
+                    data.instruction.author = Some(username.clone());
+                    data.instruction.created_at = Some(Utc::now().timestamp_millis());
+                    // Instructions using other layers capture them before being broadcast,
+                    // rendering a copy of the drawing so that it stays unlocked meanwhile.
+                    if data.instruction.instruction.uses_sources() {
+                        let drawing = app_data.drawing.lock().await.clone();
+                        let mut instruction = data.instruction.instruction;
+                        let captured = tokio::task::spawn_blocking(move || {
+                            drawing
+                                .capture_sources(&mut instruction)
+                                .map(|_| instruction)
+                        })
+                        .await;
+                        match captured {
+                            Ok(Ok(instruction)) => data.instruction.instruction = instruction,
+                            _ => continue,
+                        }
+                    }
+                    let added = app_data
                         .drawing
                         .lock()
                         .await
                         .instruct(&data.layer, data.instruction.clone())
-                        .is_ok()
-                    {
+                        .is_ok();
+                    if added {
                         let msg = Message::text(
                             serde_json::to_string(&WebSocketServerMessage::Instruction(data))
                                 .unwrap(),
@@ -195,6 +218,24 @@ pub async fn handle_socket(socket: WebSocket, username: String, app_data: Arc<Ap
                         }
                     }
                 }
+                WebSocketClientMessage::SetLayerClipping(data) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .set_clip_to_below(&data.layer, data.clip_to_below)
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::SetLayerClipping(data))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
                 WebSocketClientMessage::RequestInit => {
                     sender
                         .lock()
@@ -207,6 +248,17 @@ pub async fn handle_socket(socket: WebSocket, username: String, app_data: Arc<Ap
                             .unwrap(),
                         ))
                         .await;
+                    if let Some(replay) = &app_data.replay {
+                        let state = replay.playback.lock().await.state();
+                        sender
+                            .lock()
+                            .await
+                            .send(Message::text(
+                                serde_json::to_string(&WebSocketServerMessage::ReplayState(state))
+                                    .unwrap(),
+                            ))
+                            .await;
+                    }
                 }
                 WebSocketClientMessage::TempDraw(data) => {
                     let mut users = app_data.users.lock().await;
@@ -216,6 +268,12 @@ pub async fn handle_socket(socket: WebSocket, username: String, app_data: Arc<Ap
                             uuid: data.uuid,
                             start: data.start,
                             end: data.end,
+                            start_pressure: data.start_pressure,
+                            end_pressure: data.end_pressure,
+                            start_tilt: data.start_tilt,
+                            end_tilt: data.end_tilt,
+                            seed: data.seed,
+                            clip: data.clip,
                             layer: data.layer,
                             username: username.clone(),
                         })).unwrap(),
@@ -234,6 +292,7 @@ pub async fn handle_socket(socket: WebSocket, username: String, app_data: Arc<Ap
                                 username: username.clone(),
                                 points: selection.points,
                                 closed: selection.closed,
+                                selection: selection.selection,
                             },
                         ))
                         .unwrap(),
@@ -349,6 +408,291 @@ pub async fn handle_socket(socket: WebSocket, username: String, app_data: Arc<Ap
                         }
                     }
                 }
+                WebSocketClientMessage::AddMask(layer) => {
+                    if app_data.drawing.lock().await.add_mask(&layer).is_ok() {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::AddMask(layer)).unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::RemoveMask(layer) => {
+                    if app_data.drawing.lock().await.remove_mask(&layer).is_ok() {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::RemoveMask(layer))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::MaskInstruction(mut data) => {
+                    data.instruction.author = Some(username.clone());
+                    data.instruction.created_at = Some(Utc::now().timestamp_millis());
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .mask_instruct(&data.layer, data.instruction.clone())
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::MaskInstruction(data))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::SetMaskHistoryIndex(data) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .set_mask_history_index(&data.layer, data.new_history_index)
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::SetMaskHistoryIndex(
+                                data,
+                            ))
+                            .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::SetMaskEnabled(data) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .set_mask_enabled(&data.layer, data.enabled)
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::SetMaskEnabled(data))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::ApplyMask(data) => {
+                    let applied = match app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .apply_mask(&data.layer, data.uuid)
+                    {
+                        Ok(instruction) => {
+                            instruction.author = Some(username.clone());
+                            instruction.created_at = Some(Utc::now().timestamp_millis());
+                            Some(instruction.clone())
+                        }
+                        Err(_) => None,
+                    };
+                    if let Some(instruction) = applied {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::ApplyMask(
+                                InstructionData {
+                                    layer: data.layer,
+                                    instruction,
+                                },
+                            ))
+                            .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::AddSwatch(swatch) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .add_swatch(swatch.clone())
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::AddSwatch(swatch))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::RemoveSwatch(name) => {
+                    if app_data.drawing.lock().await.remove_swatch(&name).is_ok() {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::RemoveSwatch(name))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::MoveSwatch(data) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .move_swatch(&data.name, data.position)
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::MoveSwatch(data))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::ListBrushPresets => {
+                    let presets = BrushPresetsData {
+                        defaults: app_data.default_brushes.clone(),
+                        drawing: app_data.drawing.lock().await.brush_presets().to_vec(),
+                    };
+                    sender
+                        .lock()
+                        .await
+                        .send(Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::BrushPresets(presets))
+                                .unwrap(),
+                        ))
+                        .await;
+                }
+                WebSocketClientMessage::CreateBrushPreset(preset) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .add_brush_preset(preset.clone())
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::CreateBrushPreset(
+                                preset,
+                            ))
+                            .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::UpdateBrushPreset(preset) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .update_brush_preset(preset.clone())
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::UpdateBrushPreset(
+                                preset,
+                            ))
+                            .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::DeleteBrushPreset(name) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .remove_brush_preset(&name)
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::DeleteBrushPreset(name))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::AddPattern(pattern) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .add_pattern(pattern.clone())
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::AddPattern(pattern))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::SetSymmetry(symmetry) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .set_symmetry(symmetry.clone())
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::SetSymmetry(symmetry))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
+                WebSocketClientMessage::EditInstruction(data) => {
+                    if app_data
+                        .drawing
+                        .lock()
+                        .await
+                        .replace_instruction(&data.layer, data.index, data.instruction.clone())
+                        .is_ok()
+                    {
+                        let msg = Message::text(
+                            serde_json::to_string(&WebSocketServerMessage::EditInstruction(data))
+                                .unwrap(),
+                        );
+                        let mut users = app_data.users.lock().await;
+                        for user in users.values_mut() {
+                            user.lock().await.send(msg.clone()).await;
+                        }
+                    }
+                }
                 WebSocketClientMessage::SetInstructionVisibility(data) => {
                     if app_data
                         .drawing
@@ -387,6 +731,44 @@ pub async fn handle_socket(socket: WebSocket, username: String, app_data: Arc<Ap
                         }
                     }
                 }
+                WebSocketClientMessage::InstructionsAt(point) => {
+                    let drawing = app_data.drawing.lock().await.clone();
+                    let query = point.clone();
+                    match tokio::task::spawn_blocking(move || drawing.instructions_at(&query)).await
+                    {
+                        Ok(Ok(instructions)) => {
+                            sender
+                                .lock()
+                                .await
+                                .send(Message::text(
+                                    serde_json::to_string(&WebSocketServerMessage::InstructionsAt(
+                                        InstructionsAtData {
+                                            point,
+                                            instructions,
+                                        },
+                                    ))
+                                    .unwrap(),
+                                ))
+                                .await;
+                        }
+                        Ok(Err(e)) => error!("Could not find instructions at {point:?}: {e}"),
+                        Err(e) => error!("Could not find instructions at {point:?}: {e}"),
+                    }
+                }
+                WebSocketClientMessage::ReplayPlay => {
+                    replay::update(&app_data, |playback| playback.playing = true).await;
+                }
+                WebSocketClientMessage::ReplayPause => {
+                    replay::update(&app_data, |playback| playback.playing = false).await;
+                }
+                WebSocketClientMessage::ReplaySeek(position) => {
+                    replay::seek(&app_data, position).await;
+                }
+                WebSocketClientMessage::ReplaySpeed(speed) => {
+                    if speed.is_finite() && speed > 0.0 {
+                        replay::update(&app_data, |playback| playback.speed = speed).await;
+                    }
+                }
                 WebSocketClientMessage::KeepAlive => {},
             }
         } else {
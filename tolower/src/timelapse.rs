@@ -0,0 +1,215 @@
+use clap::{Args, ValueEnum};
+use drawing::{Drawing, Pixmap};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelapseFormat {
+    #[default]
+    Gif,
+    Apng,
+}
+
+impl TimelapseFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            TimelapseFormat::Gif => "image/gif",
+            TimelapseFormat::Apng => "image/apng",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TimelapseFormat::Gif => "gif",
+            TimelapseFormat::Apng => "png",
+        }
+    }
+}
+
+#[derive(Args, Deserialize, Clone, Debug)]
+pub struct TimelapseOptions {
+    /// Format of the animation
+    #[clap(long, value_enum, default_value_t = TimelapseFormat::Gif)]
+    #[serde(default)]
+    pub format: TimelapseFormat,
+
+    /// Number of frames shown per instruction, below 1 to skip instructions
+    #[clap(long, default_value_t = 1.0)]
+    #[serde(default = "default_frames_per_instruction")]
+    pub frames_per_instruction: f32,
+
+    /// Width of the animation, keeps the aspect ratio if only the height is set, at most the
+    /// width of the drawing
+    #[clap(long)]
+    #[serde(default)]
+    pub width: Option<u32>,
+
+    /// Height of the animation, keeps the aspect ratio if only the width is set, at most the
+    /// height of the drawing
+    #[clap(long)]
+    #[serde(default)]
+    pub height: Option<u32>,
+
+    /// Duration of a frame in milliseconds
+    #[clap(long, default_value_t = 100)]
+    #[serde(default = "default_delay")]
+    pub delay: u16,
+}
+
+fn default_frames_per_instruction() -> f32 {
+    1.0
+}
+
+fn default_delay() -> u16 {
+    100
+}
+
+#[derive(Error, Debug)]
+pub enum TimelapseError {
+    #[error("drawing error: {0}")]
+    Drawing(#[from] drawing::DrawingError),
+    #[error("render error: {0}")]
+    Render(#[from] drawing::RenderError),
+    #[error("invalid size {0}x{1}")]
+    InvalidSize(u32, u32),
+    #[error("invalid number of frames per instruction {0}")]
+    InvalidFramesPerInstruction(f32),
+    #[error("gif error: {0}")]
+    Gif(#[from] gif::EncodingError),
+    #[error("png error: {0}")]
+    Png(#[from] png::EncodingError),
+}
+
+/// Renders the construction of the drawing as an animation.
+///
+/// The animation cannot be larger than the drawing.
+pub fn export(drawing: &Drawing, options: &TimelapseOptions) -> Result<Vec<u8>, TimelapseError> {
+    let (width, height) = size(drawing, options);
+    if width == 0
+        || height == 0
+        || width > drawing.width().min(u16::MAX as u32)
+        || height > drawing.height().min(u16::MAX as u32)
+    {
+        return Err(TimelapseError::InvalidSize(width, height));
+    }
+    if !options.frames_per_instruction.is_finite() || options.frames_per_instruction < 0.0 {
+        return Err(TimelapseError::InvalidFramesPerInstruction(
+            options.frames_per_instruction,
+        ));
+    }
+    let timelapse = drawing.timelapse()?;
+    let durations = frame_durations(timelapse.len(), options.frames_per_instruction);
+    let frame_count = durations.iter().filter(|d| **d > 0).count();
+
+    let mut out = vec![];
+    match options.format {
+        TimelapseFormat::Gif => {
+            let mut encoder = gif::Encoder::new(&mut out, width as u16, height as u16, &[])?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            for (frame, duration) in timelapse.zip(durations) {
+                let frame = frame?;
+                if duration == 0 {
+                    continue;
+                }
+                let mut rgba = rgba(&scale(&frame, width, height)?);
+                let mut frame =
+                    gif::Frame::from_rgba_speed(width as u16, height as u16, &mut rgba, 10);
+                frame.delay = (duration.saturating_mul(options.delay as u32) / 10)
+                    .min(u16::MAX as u32) as u16;
+                frame.dispose = gif::DisposalMethod::Background;
+                encoder.write_frame(&frame)?;
+            }
+        }
+        TimelapseFormat::Apng => {
+            let mut encoder = png::Encoder::new(&mut out, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(frame_count.max(1) as u32, 0)?;
+            let mut writer = encoder.write_header()?;
+            let mut written = 0;
+            for (frame, duration) in timelapse.zip(durations) {
+                let frame = frame?;
+                if duration == 0 {
+                    continue;
+                }
+                let delay = duration
+                    .saturating_mul(options.delay as u32)
+                    .min(u16::MAX as u32) as u16;
+                writer.set_frame_delay(delay, 1000)?;
+                writer.write_image_data(&rgba(&scale(&frame, width, height)?))?;
+                written += 1;
+            }
+            if written == 0 {
+                writer.write_image_data(&vec![0; (width * height * 4) as usize])?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(out)
+}
+
+/// Computes the size of the animation from the options and the drawing size.
+fn size(drawing: &Drawing, options: &TimelapseOptions) -> (u32, u32) {
+    let ratio = drawing.width() as f32 / drawing.height() as f32;
+    match (options.width, options.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width as f32 / ratio).round() as u32),
+        (None, Some(height)) => ((height as f32 * ratio).round() as u32, height),
+        (None, None) => (drawing.width(), drawing.height()),
+    }
+}
+
+/// Computes how many frames each instruction is shown for.
+///
+/// Fractional frames are carried over to the next instructions, and the
+/// last instruction is always shown so that the animation ends on the
+/// finished drawing.
+fn frame_durations(instructions: usize, frames_per_instruction: f32) -> Vec<u32> {
+    let mut carry = 0.0;
+    let mut durations: Vec<u32> = (0..instructions)
+        .map(|_| {
+            carry += frames_per_instruction.max(0.0);
+            let frames = carry.floor();
+            carry -= frames;
+            frames as u32
+        })
+        .collect();
+    if let Some(last) = durations.last_mut() {
+        *last = (*last).max(1);
+    }
+    durations
+}
+
+fn scale(frame: &Pixmap, width: u32, height: u32) -> Result<Pixmap, TimelapseError> {
+    if frame.width() == width && frame.height() == height {
+        return Ok(frame.clone());
+    }
+    let mut scaled = Pixmap::new(width, height).ok_or(TimelapseError::InvalidSize(width, height))?;
+    scaled.draw_pixmap(
+        0,
+        0,
+        frame.as_ref(),
+        &tiny_skia::PixmapPaint {
+            quality: tiny_skia::FilterQuality::Bilinear,
+            ..Default::default()
+        },
+        tiny_skia::Transform::from_scale(
+            width as f32 / frame.width() as f32,
+            height as f32 / frame.height() as f32,
+        ),
+        None,
+    );
+    Ok(scaled)
+}
+
+fn rgba(frame: &Pixmap) -> Vec<u8> {
+    frame
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect()
+}
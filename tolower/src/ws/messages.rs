@@ -1,6 +1,8 @@
 use drawing::{
     instruction::{Instruction, InstructionBox},
-    Color, ImageInsertion, Motion, Point, Stroke,
+    BrushPreset,
+    Color, ImageInsertion, InstructionHit, Motion, Palette, Pattern, Point, Selection, Stroke, Swatch,
+    Symmetry,
 };
 use serde::{Deserialize, Serialize};
 
@@ -10,6 +12,7 @@ pub enum WebSocketClientMessage {
     Cursor(CursorClientData),
     Instruction(InstructionData),
     SetLayerVisibility(SetLayerVisibilityData),
+    SetLayerClipping(SetLayerClippingData),
     AddLayer(String),
     LayerUp(String),
     LayerDown(String),
@@ -26,6 +29,29 @@ pub enum WebSocketClientMessage {
     Snapshot(SnapshotData),
     SetInstructionVisibility(SetInstructionVisibilityData),
     RemoveInstruction(RemoveInstructionData),
+    EditInstruction(EditInstructionData),
+    AddMask(String),
+    RemoveMask(String),
+    MaskInstruction(InstructionData),
+    SetMaskHistoryIndex(SetHistoryIndexData),
+    SetMaskEnabled(SetMaskEnabledData),
+    ApplyMask(ApplyMaskClientData),
+    AddSwatch(Swatch),
+    RemoveSwatch(String),
+    MoveSwatch(MoveSwatchData),
+    ListBrushPresets,
+    CreateBrushPreset(BrushPreset),
+    UpdateBrushPreset(BrushPreset),
+    DeleteBrushPreset(String),
+    /// Adds a pattern that bucket fills can repeat by its id.
+    AddPattern(Pattern),
+    /// Changes the symmetry of the drawing, `None` turning it off.
+    SetSymmetry(Option<Symmetry>),
+    InstructionsAt(Point),
+    ReplayPlay,
+    ReplayPause,
+    ReplaySeek(u64),
+    ReplaySpeed(f32),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -33,6 +59,7 @@ pub enum WebSocketServerMessage {
     Cursor(CursorServerData),
     Instruction(InstructionData),
     SetLayerVisibility(SetLayerVisibilityData),
+    SetLayerClipping(SetLayerClippingData),
     AddLayer(String),
     LayerUp(String),
     LayerDown(String),
@@ -51,6 +78,28 @@ pub enum WebSocketServerMessage {
     Snapshot(SnapshotData),
     SetInstructionVisibility(SetInstructionVisibilityData),
     RemoveInstruction(RemoveInstructionData),
+    EditInstruction(EditInstructionData),
+    AddMask(String),
+    RemoveMask(String),
+    MaskInstruction(InstructionData),
+    SetMaskHistoryIndex(SetHistoryIndexData),
+    SetMaskEnabled(SetMaskEnabledData),
+    /// The mask of the layer was removed and the instruction applying it was added.
+    ApplyMask(InstructionData),
+    AddSwatch(Swatch),
+    RemoveSwatch(String),
+    MoveSwatch(MoveSwatchData),
+    /// The whole palette was replaced.
+    SetPalette(Palette),
+    BrushPresets(BrushPresetsData),
+    CreateBrushPreset(BrushPreset),
+    UpdateBrushPreset(BrushPreset),
+    DeleteBrushPreset(String),
+    AddPattern(Pattern),
+    SetSymmetry(Option<Symmetry>),
+    InstructionsAt(InstructionsAtData),
+    SetInstructionsVisibility(Vec<SetInstructionVisibilityData>),
+    ReplayState(ReplayStateData),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -65,6 +114,12 @@ pub struct SetLayerVisibilityData {
     pub visible: bool,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetLayerClippingData {
+    pub layer: String,
+    pub clip_to_below: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SetHistoryIndexData {
     pub layer: String,
@@ -115,6 +170,22 @@ pub struct TempDrawClientData {
     pub uuid: String,
     pub start: drawing::Point,
     pub end: drawing::Point,
+    /// Stylus pressure at `start` and `end`, omitted for devices without pressure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_pressure: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_pressure: Option<u16>,
+    /// Stylus tilt at `start` and `end`, omitted for devices without tilt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_tilt: Option<drawing::Tilt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_tilt: Option<drawing::Tilt>,
+    /// The seed of the stroke, for brushes with jitter.
+    #[serde(default)]
+    pub seed: u32,
+    /// The selection the stroke is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
     pub layer: String,
 }
 
@@ -124,6 +195,22 @@ pub struct TempDrawServerData {
     pub uuid: String,
     pub start: drawing::Point,
     pub end: drawing::Point,
+    /// Stylus pressure at `start` and `end`, omitted for devices without pressure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_pressure: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_pressure: Option<u16>,
+    /// Stylus tilt at `start` and `end`, omitted for devices without tilt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_tilt: Option<drawing::Tilt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_tilt: Option<drawing::Tilt>,
+    /// The seed of the stroke, for brushes with jitter.
+    #[serde(default)]
+    pub seed: u32,
+    /// The selection the stroke is clipped to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Selection>,
     pub layer: String,
     pub username: String,
 }
@@ -132,6 +219,9 @@ pub struct TempDrawServerData {
 pub struct SelectionClientData {
     pub points: Vec<Point>,
     pub closed: bool,
+    /// The whole selection, when it is not only the lasso going through `points`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -139,6 +229,8 @@ pub struct SelectionServerData {
     pub username: String,
     pub points: Vec<Point>,
     pub closed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -179,7 +271,7 @@ pub struct TempImageServerData {
 pub struct MoveStartClientData {
     pub uuid: String,
     pub layer: String,
-    pub selection: Vec<Point>,
+    pub selection: Selection,
     pub end: Point,
     pub scale: Point,
     pub rotate: u32,
@@ -199,7 +291,7 @@ pub struct MoveStartServerData {
     pub username: String,
     pub uuid: String,
     pub layer: String,
-    pub selection: Vec<Point>,
+    pub selection: Selection,
     pub end: Point,
     pub scale: Point,
     pub rotate: u32,
@@ -235,6 +327,89 @@ pub struct RemoveInstructionData {
     pub index: u64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EditInstructionData {
+    pub layer: String,
+    pub index: u64,
+    pub instruction: Instruction,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetMaskEnabledData {
+    pub layer: String,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApplyMaskClientData {
+    pub layer: String,
+    /// The uuid of the instruction applying the mask.
+    pub uuid: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MoveSwatchData {
+    pub name: String,
+    pub position: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BrushPresetsData {
+    /// Presets offered by the server in every drawing, which cannot be edited.
+    pub defaults: Vec<BrushPreset>,
+    /// Presets of the drawing.
+    pub drawing: Vec<BrushPreset>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstructionsAtData {
+    pub point: Point,
+    pub instructions: Vec<InstructionHit>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplayStateData {
+    pub playing: bool,
+    pub position: u64,
+    pub length: u64,
+    pub speed: f32,
+}
+
+impl WebSocketClientMessage {
+    /// Returns true if the message modifies the drawing.
+    pub fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            WebSocketClientMessage::Instruction(_)
+                | WebSocketClientMessage::SetLayerVisibility(_)
+                | WebSocketClientMessage::SetLayerClipping(_)
+                | WebSocketClientMessage::AddLayer(_)
+                | WebSocketClientMessage::LayerUp(_)
+                | WebSocketClientMessage::LayerDown(_)
+                | WebSocketClientMessage::SetHistoryIndex(_)
+                | WebSocketClientMessage::MoveInstruction(_)
+                | WebSocketClientMessage::Snapshot(_)
+                | WebSocketClientMessage::SetInstructionVisibility(_)
+                | WebSocketClientMessage::RemoveInstruction(_)
+                | WebSocketClientMessage::EditInstruction(_)
+                | WebSocketClientMessage::AddMask(_)
+                | WebSocketClientMessage::RemoveMask(_)
+                | WebSocketClientMessage::MaskInstruction(_)
+                | WebSocketClientMessage::SetMaskHistoryIndex(_)
+                | WebSocketClientMessage::SetMaskEnabled(_)
+                | WebSocketClientMessage::ApplyMask(_)
+                | WebSocketClientMessage::AddSwatch(_)
+                | WebSocketClientMessage::RemoveSwatch(_)
+                | WebSocketClientMessage::MoveSwatch(_)
+                | WebSocketClientMessage::CreateBrushPreset(_)
+                | WebSocketClientMessage::UpdateBrushPreset(_)
+                | WebSocketClientMessage::DeleteBrushPreset(_)
+                | WebSocketClientMessage::AddPattern(_)
+                | WebSocketClientMessage::SetSymmetry(_)
+        )
+    }
+}
+
 impl CursorServerData {
     pub fn from_recieved(cursor: CursorClientData, username: String) -> Self {
         CursorServerData { cursor, username }